@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS sudo_audit (
+    id BIGSERIAL PRIMARY KEY,
+    sub TEXT NOT NULL,
+    account TEXT NOT NULL,
+    call TEXT NOT NULL,
+    args TEXT NOT NULL,
+    status TEXT NOT NULL,
+    tx_hash TEXT,
+    error TEXT,
+    created_at BIGINT NOT NULL,
+    updated_at BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS sudo_audit_sub ON sudo_audit (sub);
@@ -0,0 +1,8 @@
+-- Amounts are decimal text, they do not fit in BIGINT
+CREATE TABLE IF NOT EXISTS asset_issuance (
+    class_id TEXT NOT NULL,
+    asset_id TEXT NOT NULL,
+    minted TEXT NOT NULL,
+    burned TEXT NOT NULL,
+    PRIMARY KEY (class_id, asset_id)
+);
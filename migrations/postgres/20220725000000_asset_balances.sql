@@ -0,0 +1,15 @@
+-- Amounts are decimal text zero padded to 39 digits, the width of u128::MAX,
+-- so that they sort as numbers
+CREATE TABLE IF NOT EXISTS asset_balances (
+    class_id TEXT NOT NULL,
+    asset_id TEXT NOT NULL,
+    account TEXT NOT NULL,
+    amount TEXT NOT NULL,
+    PRIMARY KEY (class_id, asset_id, account)
+);
+
+CREATE INDEX IF NOT EXISTS asset_balances_amount ON asset_balances (class_id, asset_id, amount, account);
+
+-- Databases indexed before this table only have the balances of the blocks indexed from now on
+INSERT INTO indexer_state (name, block_number, block_hash)
+SELECT 'balances_start', block_number + 1, '' FROM indexer_state WHERE name = 'events';
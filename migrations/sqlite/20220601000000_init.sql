@@ -0,0 +1,53 @@
+CREATE TABLE IF NOT EXISTS transactions (
+    job_id TEXT PRIMARY KEY,
+    tx_hash TEXT NOT NULL,
+    account TEXT NOT NULL,
+    status TEXT NOT NULL,
+    block_hash TEXT,
+    events TEXT,
+    error TEXT,
+    created_at BIGINT NOT NULL,
+    updated_at BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS transactions_tx_hash ON transactions (tx_hash);
+
+CREATE TABLE IF NOT EXISTS idempotency_keys (
+    key TEXT PRIMARY KEY,
+    created_at BIGINT NOT NULL,
+    status INTEGER,
+    content_type TEXT,
+    body TEXT
+);
+
+CREATE TABLE IF NOT EXISTS events (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    block_number BIGINT NOT NULL,
+    block_hash TEXT NOT NULL,
+    extrinsic_index INTEGER,
+    event_index INTEGER NOT NULL,
+    pallet TEXT NOT NULL,
+    variant TEXT NOT NULL,
+    class_id BIGINT,
+    asset_id BIGINT,
+    data TEXT NOT NULL,
+    timestamp BIGINT
+);
+
+CREATE INDEX IF NOT EXISTS events_block_number ON events (block_number);
+CREATE INDEX IF NOT EXISTS events_pallet_variant ON events (pallet, variant);
+CREATE INDEX IF NOT EXISTS events_class_asset ON events (class_id, asset_id);
+
+CREATE TABLE IF NOT EXISTS event_accounts (
+    event_id BIGINT NOT NULL REFERENCES events (id),
+    account TEXT NOT NULL,
+    PRIMARY KEY (event_id, account)
+);
+
+CREATE INDEX IF NOT EXISTS event_accounts_account ON event_accounts (account, event_id);
+
+CREATE TABLE IF NOT EXISTS user_metadata (
+    sub TEXT PRIMARY KEY,
+    metadata TEXT NOT NULL,
+    updated_at BIGINT NOT NULL
+);
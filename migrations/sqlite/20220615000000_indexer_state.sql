@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS indexer_state (
+    name TEXT PRIMARY KEY,
+    block_number BIGINT NOT NULL,
+    block_hash TEXT NOT NULL
+);
@@ -1,10 +1,10 @@
+use crate::db::{map_db_err, require_db, EventFilter};
 use crate::state::*;
 use crate::sugarfunge;
-use crate::user::get_sugarfunge_token;
 use crate::util::*;
+use crate::signer;
+use crate::tx;
 use crate::user;
-use crate::config::Config;
-use actix_web::http::header;
 use actix_web::{error, web, HttpRequest, http::StatusCode, HttpResponse};
 use rand::prelude::*;
 use serde::{Deserialize, Serialize};
@@ -12,7 +12,6 @@ use serde_json::json;
 use sp_core::Pair;
 use std::str::FromStr;
 use subxt::sp_runtime::traits::IdentifyAccount;
-use subxt::PairSigner;
 use actix_web_middleware_keycloak_auth::KeycloakClaims;
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -21,11 +20,16 @@ pub struct CreateAccountOutput {
     account: String,
 }
 
-/// Generate a unique seed and its associated account
-pub async fn create(_req: HttpRequest) -> error::Result<HttpResponse> {
+/// Generate a unique seed
+pub fn generate_seed() -> String {
     let seed = rand::thread_rng().gen::<[u8; 32]>();
     let seed = hex::encode(seed);
-    let seed = format!("//{}", seed);
+    format!("//{}", seed)
+}
+
+/// Generate a unique seed and its associated account
+pub async fn create(_req: HttpRequest) -> error::Result<HttpResponse> {
+    let seed = generate_seed();
     let pair = get_pair_from_seed(&seed)?;
     let account = pair.public().into_account();
     Ok(HttpResponse::build(StatusCode::OK).json(CreateAccountOutput {
@@ -51,54 +55,38 @@ pub struct FundAccountOutput {
 pub async fn fund(
     data: web::Data<AppState>,
     req: web::Json<FundAccountInput>,
+    query: web::Query<tx::WaitQuery>,
     claims: KeycloakClaims<user::ClaimsWithEmail>,
-    env: web::Data<Config>
 ) -> error::Result<HttpResponse> {
-    match user::get_seed(&claims.sub, env).await {
-        Ok(response) => {
-            if !response.seed.clone().unwrap_or_default().is_empty() {
-                let user_seed = response.seed.clone().unwrap();
-
-                let pair = get_pair_from_seed(&user_seed)?;
-                let signer = PairSigner::new(pair);
-                let account = sp_core::sr25519::Public::from_str(&req.to).map_err(map_account_err)?;
-                let account = sp_core::crypto::AccountId32::from(account);
-                let account = subxt::sp_runtime::MultiAddress::Id(account);
-                let amount_input = req.amount;
-                let api = data.api.lock().unwrap();
-                let result = api
-                    .tx()
-                    .balances()
-                    .transfer(account, amount_input)
-                    .sign_and_submit_then_watch(&signer)
-                    .await
-                    .map_err(map_subxt_err)?
-                    .wait_for_finalized_success()
-                    .await
-                    .map_err(map_subxt_err)?;
-                let result = result
-                    .find_first_event::<sugarfunge::balances::events::Transfer>()
-                    .map_err(map_subxt_err)?;
-                match result {
-                    Some(event) => Ok(HttpResponse::Ok().json(FundAccountOutput {
-                        from: event.from.to_string(),
-                        to: event.to.to_string(),
-                        amount: event.amount,
-                    })),
-                    None => Ok(HttpResponse::BadRequest().json(RequestError {
-                        message: json!("Failed to find sugarfunge::balances::events::Transfer"),
-                    })),
-                }
-
-            } else {
-                Ok(HttpResponse::BadRequest().json(RequestError {
-                    message: json!("Not found user Attributes"),
-                }))
-            }
-        },
-        Err(_) => Ok(HttpResponse::BadRequest().json(RequestError {
-            message: json!("Failed to find user::getAttributes"),
-        }))
+    let account = sp_core::sr25519::Public::from_str(&req.to).map_err(map_account_err)?;
+    let account = sp_core::crypto::AccountId32::from(account);
+    let account = subxt::sp_runtime::MultiAddress::Id(account);
+    let signer = signer::pair_signer(&data, &claims.sub).await?;
+    let amount_input = req.amount;
+    let api = data.api.get();
+    let result = api
+        .tx()
+        .balances()
+        .transfer(account, amount_input)
+        .sign_and_submit_then_watch(&signer)
+        .await
+        .map_err(data.nonces.map_err(&signer))?;
+    let result = match tx::wait(&data, &claims.sub, &signer, result, query.wait).await? {
+        tx::Submitted::Done(events) => events,
+        tx::Submitted::Pending(job) => return Ok(HttpResponse::Accepted().json(job)),
+    };
+    let result = result
+        .find_first_event::<sugarfunge::balances::events::Transfer>()
+        .map_err(map_subxt_err)?;
+    match result {
+        Some(event) => Ok(HttpResponse::Ok().json(FundAccountOutput {
+            from: event.from.to_string(),
+            to: event.to.to_string(),
+            amount: event.amount,
+        })),
+        None => Ok(HttpResponse::BadRequest().json(RequestError {
+            message: json!("Failed to find sugarfunge::balances::events::Transfer"),
+        })),
     }
 }
 
@@ -119,7 +107,7 @@ pub async fn balance(
 ) -> error::Result<HttpResponse> {
     let account = sp_core::sr25519::Public::from_str(&req.account).map_err(map_account_err)?;
     let account = sp_core::crypto::AccountId32::from(account);
-    let api = data.api.lock().unwrap();
+    let api = data.api.get();
     let result = api.storage().system().account(account, None).await;
     let data = result.map_err(map_subxt_err)?;
     Ok(HttpResponse::Ok().json(AccountBalanceOutput {
@@ -138,108 +126,118 @@ pub struct TransferAccountOutput {
     message: String
 }
 
+/// Move the account of the logged in user to another user
 pub async fn transfer(
+    data: web::Data<AppState>,
     req: web::Json<TransferAccountInput>,
     claims: KeycloakClaims<user::ClaimsWithEmail>,
-    env: web::Data<Config>
 ) -> error::Result<HttpResponse> {
-    let config = env.clone();
-    let env_aux = env.clone();
-    match user::get_seed(&claims.sub, env).await {
-        Ok(response) => {
-            if !response.seed.clone().unwrap_or_default().is_empty() {
-                let user_seed = response.seed.clone().unwrap();
-
-                match get_sugarfunge_token(env_aux).await {
-                    Ok(response) => {
-                        
-                        let awc_client = awc::Client::new();
-                        let endpoint = format!("{}/auth/admin/realms/{}/users/{}", config.keycloak_host, config.keycloak_realm, &claims.sub); 
-
-                        let attributes = json!({
-                            "attributes": ""
-                        });
-            
-                        let user_response = awc_client.put(endpoint)
-                            .append_header((header::ACCEPT, "application/json"), )
-                            .append_header((header::CONTENT_TYPE, "application/json"))
-                            .append_header((header::AUTHORIZATION, "Bearer ".to_string() + &response.access_token))
-                            .send_json(&attributes)
-                            .await;
-            
-                        match user_response {
-                            Ok(user_response) => {
-                                if let StatusCode::NO_CONTENT = user_response.status() {
-                                    
-                                    let awc_client = awc::Client::new();
-                                    let endpoint = format!("{}/auth/admin/realms/{}/users/{}", config.keycloak_host, config.keycloak_realm, req.to); 
-
-                                    let attributes = json!({
-                                        "attributes": {
-                                            "user-seed": [
-                                                user_seed
-                                            ]
-                                        }
-                                    });
-                        
-                                    let receiver_response = awc_client.put(endpoint)
-                                        .append_header((header::ACCEPT, "application/json"), )
-                                        .append_header((header::CONTENT_TYPE, "application/json"))
-                                        .append_header((header::AUTHORIZATION, "Bearer ".to_string() + &response.access_token))
-                                        .send_json(&attributes)
-                                        .await;
-
-                                    match receiver_response {
-                                        Ok(receiver_response) => {
-                                            if let StatusCode::NO_CONTENT = receiver_response.status() {
-                                                Ok(HttpResponse::Ok().json(
-                                                    TransferAccountOutput {
-                                                        error: None,
-                                                        message: "Attribute insert to user attributes".to_string()
-                                                    }
-                                                ))
-                                            } 
-                                            else {
-                                                Ok(HttpResponse::BadRequest().json(RequestError {
-                                                    message: json!("Failed to find user::getAttributes"),
-                                                }))
-                                            }
-                                        }
-                                        Err(_e) => {
-                                            Ok(HttpResponse::BadRequest().json(RequestError {
-                                                    message: json!("Failed to find user::getAttributes"),
-                                                }))
-                                            }                                        
-                                    }    
-                                                                       
-                                } else {
-                                    Ok(HttpResponse::BadRequest().json(RequestError {
-                                        message: json!("Failed to find user::getAttributes"),
-                                    }))
-                                }
-                            }                
-                            Err(_e) => {
-                            Ok(HttpResponse::BadRequest().json(RequestError {
-                                    message: json!("Failed to find user::getAttributes"),
-                                }))
-                            }
-                        }
-
-                    }
-                    Err(_e) => {
-                        Ok(HttpResponse::BadRequest().json(RequestError {
-                                message: json!("Failed to find user::getAttributes"),
-                            }))
-                        }
-                }
-            } else {
-                Ok(HttpResponse::BadRequest().json(RequestError {
-                    message: json!("Not found user Attributes"),
-                }))
-            }
-        },
-        Err(_) => Ok(HttpResponse::BadRequest().json(RequestError {
-            message: json!("Failed to find user::getAttributes"),
-        }))
-    }
-}
\ No newline at end of file
+    data.signer.transfer(&claims.sub, &req.to).await?;
+    Ok(HttpResponse::Ok().json(TransferAccountOutput {
+        error: None,
+        message: "Attribute insert to user attributes".to_string(),
+    }))
+}
+
+/// Events shown in the activity history when no pallet or event type is given
+const HISTORY_EVENTS: &[(&str, &str)] = &[
+    ("Asset", "Transferred"),
+    ("Asset", "Mint"),
+    ("Asset", "Burn"),
+    ("Dex", "CurrencyToAsset"),
+    ("Dex", "AssetToCurrency"),
+    ("Escrow", "Deposit"),
+    ("Escrow", "Refund"),
+    ("Bundle", "Mint"),
+    ("Bundle", "Burn"),
+];
+
+const HISTORY_LIMIT: u32 = 50;
+const HISTORY_MAX_LIMIT: u32 = 500;
+
+#[derive(Serialize, Deserialize)]
+pub struct AccountHistoryInput {
+    account: String,
+    pallet: Option<String>,
+    event: Option<String>,
+    class_id: Option<u64>,
+    asset_id: Option<u64>,
+    from_block: Option<u32>,
+    to_block: Option<u32>,
+    cursor: Option<i64>,
+    limit: Option<u32>,
+}
+
+#[derive(Serialize)]
+pub struct AccountHistoryItemOutput {
+    block_number: u32,
+    block_hash: String,
+    extrinsic_index: Option<u32>,
+    event_index: u32,
+    timestamp: Option<u64>,
+    pallet: String,
+    event: String,
+    data: Box<serde_json::value::RawValue>,
+}
+
+#[derive(Serialize)]
+pub struct AccountHistoryOutput {
+    history: Vec<AccountHistoryItemOutput>,
+    next_cursor: Option<i64>,
+}
+
+/// Get the indexed activity of an account, newest first
+pub async fn history(
+    data: web::Data<AppState>,
+    req: web::Json<AccountHistoryInput>,
+) -> error::Result<HttpResponse> {
+    let db = require_db(&data.db)?;
+    let account = sp_core::sr25519::Public::from_str(&req.account).map_err(map_account_err)?;
+    let account = sp_core::crypto::AccountId32::from(account);
+    let limit = req.limit.unwrap_or(HISTORY_LIMIT).clamp(1, HISTORY_MAX_LIMIT);
+    let kinds = if req.pallet.is_none() && req.event.is_none() {
+        HISTORY_EVENTS
+            .iter()
+            .map(|(pallet, variant)| (pallet.to_string(), variant.to_string()))
+            .collect()
+    } else {
+        vec![]
+    };
+    let filter = EventFilter {
+        account: Some(account.to_string()),
+        kinds,
+        pallet: req.pallet.clone(),
+        variant: req.event.clone(),
+        class_id: req.class_id,
+        asset_id: req.asset_id,
+        from_block: req.from_block,
+        to_block: req.to_block,
+        before: req.cursor,
+        limit,
+    };
+    let rows = db.events(&filter).await.map_err(map_db_err)?;
+    let next_cursor = match rows.last() {
+        Some(row) if rows.len() == limit as usize => Some(row.id),
+        _ => None,
+    };
+    let history = rows
+        .into_iter()
+        .map(|row| {
+            Ok(AccountHistoryItemOutput {
+                block_number: row.block_number as u32,
+                block_hash: row.block_hash,
+                extrinsic_index: row.extrinsic_index.map(|index| index as u32),
+                event_index: row.event_index as u32,
+                timestamp: row.timestamp.map(|timestamp| timestamp as u64),
+                pallet: row.pallet,
+                event: row.variant,
+                data: serde_json::value::RawValue::from_string(row.data)?,
+            })
+        })
+        .collect::<Result<Vec<_>, serde_json::Error>>()
+        .map_err(error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(AccountHistoryOutput {
+        history,
+        next_cursor,
+    }))
+}
@@ -1,14 +1,24 @@
+use crate::blob;
+use crate::db::{map_db_err, require_db, Database, EventFilter};
 use crate::state::*;
 use crate::sugarfunge;
 use crate::util::*;
 use crate::user;
-use crate::config::Config;
+use crate::policy;
+use crate::schema;
+use crate::signer;
+use crate::storage;
+use crate::tx;
 use actix_web_middleware_keycloak_auth::KeycloakClaims;
-use actix_web::{error, web, HttpResponse};
+use actix_web::{error, http::header, web, HttpRequest, HttpResponse};
+use codec::Decode;
+use futures::StreamExt;
+use sp_core::Pair;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use std::collections::BTreeMap;
 use std::str::FromStr;
-use subxt::PairSigner;
+use sugarfunge::runtime_types::sugarfunge_asset::Class;
 
 #[derive(Serialize, Deserialize)]
 pub struct CreateClassInput {
@@ -27,53 +37,43 @@ pub struct CreateClassOutput {
 pub async fn create_class(
     data: web::Data<AppState>,
     req: web::Json<CreateClassInput>,
+    query: web::Query<tx::WaitQuery>,
     claims: KeycloakClaims<user::ClaimsWithEmail>,
-    env: web::Data<Config>
 ) -> error::Result<HttpResponse> {
-    match user::get_seed(&claims.sub, env).await {
-        Ok(response) => {
-            if !response.seed.clone().unwrap_or_default().is_empty() {
-                let user_seed = response.seed.clone().unwrap();
-
-                let pair = get_pair_from_seed(&user_seed)?;
-                let signer = PairSigner::new(pair);
-                let to = sp_core::sr25519::Public::from_str(&req.owner).map_err(map_account_err)?;
-                let to = sp_core::crypto::AccountId32::from(to);
-                let metadata: Vec<u8> = serde_json::to_vec(&req.metadata).unwrap_or_default();
-                let api = data.api.lock().unwrap();
-                let result = api
-                    .tx()
-                    .asset()
-                    .create_class(to, req.class_id, metadata)
-                    .sign_and_submit_then_watch(&signer)
-                    .await
-                    .map_err(map_subxt_err)?
-                    .wait_for_finalized_success()
-                    .await
-                    .map_err(map_subxt_err)?;
-            
-                let result = result
-                    .find_first_event::<sugarfunge::asset::events::ClassCreated>()
-                    .map_err(map_subxt_err)?;
-            
-                match result {
-                    Some(event) => Ok(HttpResponse::Ok().json(CreateClassOutput {
-                        class_id: event.class_id,
-                        who: event.who.to_string(),
-                    })),
-                    None => Ok(HttpResponse::BadRequest().json(RequestError {
-                        message: json!("Failed to find sugarfunge::asset::events::ClassCreated"),
-                    })),
-                }
-            } else {
-                Ok(HttpResponse::BadRequest().json(RequestError {
-                    message: json!("Not found user Attributes"),
-                }))
-            }
-        },
-        Err(_) => Ok(HttpResponse::BadRequest().json(RequestError {
-            message: json!("Failed to find user::getAttributes"),
-        }))
+    let to = sp_core::sr25519::Public::from_str(&req.owner).map_err(map_account_err)?;
+    let to = sp_core::crypto::AccountId32::from(to);
+    let checked = schema::check_class_metadata(&req.metadata)
+        .and_then(|_| policy::check_class_metadata(&req.metadata));
+    if let Err(response) = checked {
+        return Ok(response);
+    }
+    let signer = signer::pair_signer(&data, &claims.sub).await?;
+    let metadata: Vec<u8> = serde_json::to_vec(&req.metadata).unwrap_or_default();
+    let api = data.api.get();
+    let result = api
+        .tx()
+        .asset()
+        .create_class(to, req.class_id, metadata)
+        .sign_and_submit_then_watch(&signer)
+        .await
+        .map_err(data.nonces.map_err(&signer))?;
+    let result = match tx::wait(&data, &claims.sub, &signer, result, query.wait).await? {
+        tx::Submitted::Done(events) => events,
+        tx::Submitted::Pending(job) => return Ok(HttpResponse::Accepted().json(job)),
+    };
+
+    let result = result
+        .find_first_event::<sugarfunge::asset::events::ClassCreated>()
+        .map_err(map_subxt_err)?;
+
+    match result {
+        Some(event) => Ok(HttpResponse::Ok().json(CreateClassOutput {
+            class_id: event.class_id,
+            who: event.who.to_string(),
+        })),
+        None => Ok(HttpResponse::BadRequest().json(RequestError {
+            message: json!("Failed to find sugarfunge::asset::events::ClassCreated"),
+        })),
     }
 }
 
@@ -82,6 +82,8 @@ pub struct CreateInput {
     class_id: u64,
     asset_id: u64,
     metadata: serde_json::Value,
+    #[serde(default)]
+    offchain: bool,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -95,54 +97,49 @@ pub struct CreateOutput {
 pub async fn create(
     data: web::Data<AppState>,
     req: web::Json<CreateInput>,
+    query: web::Query<tx::WaitQuery>,
     claims: KeycloakClaims<user::ClaimsWithEmail>,
-    env: web::Data<Config>
 ) -> error::Result<HttpResponse> {
+    let api = data.api.get();
+    let class = api
+        .storage()
+        .asset()
+        .classes(req.class_id, None)
+        .await
+        .map_err(map_subxt_err)?;
+    if let Some(class) = class {
+        let class_metadata = decode_metadata(&class.metadata);
+        if let Err(response) = schema::validate_asset_metadata(&class_metadata, &req.metadata) {
+            return Ok(response);
+        }
+    }
+    let metadata = asset_metadata_bytes(&data, &req.metadata, req.offchain).await?;
+    let signer = signer::pair_signer(&data, &claims.sub).await?;
+    let result = api
+        .tx()
+        .asset()
+        .create_asset(req.class_id, req.asset_id, metadata)
+        .sign_and_submit_then_watch(&signer)
+        .await
+        .map_err(data.nonces.map_err(&signer))?;
+    let result = match tx::wait(&data, &claims.sub, &signer, result, query.wait).await? {
+        tx::Submitted::Done(events) => events,
+        tx::Submitted::Pending(job) => return Ok(HttpResponse::Accepted().json(job)),
+    };
 
-    match user::get_seed(&claims.sub, env).await {
-        Ok(response) => {
-            if !response.seed.clone().unwrap_or_default().is_empty() {
-                let user_seed = response.seed.clone().unwrap();
-
-                let pair = get_pair_from_seed(&user_seed)?;
-                let signer = PairSigner::new(pair);
-                let metadata: Vec<u8> = serde_json::to_vec(&req.metadata).unwrap_or_default();
-                let api = data.api.lock().unwrap();
-                let result = api
-                    .tx()
-                    .asset()
-                    .create_asset(req.class_id, req.asset_id, metadata)
-                    .sign_and_submit_then_watch(&signer)
-                    .await
-                    .map_err(map_subxt_err)?
-                    .wait_for_finalized_success()
-                    .await
-                    .map_err(map_subxt_err)?;
-            
-                let result = result
-                    .find_first_event::<sugarfunge::asset::events::AssetCreated>()
-                    .map_err(map_subxt_err)?;
-            
-                match result {
-                    Some(event) => Ok(HttpResponse::Ok().json(CreateOutput {
-                        class_id: event.class_id,
-                        asset_id: event.asset_id,
-                        who: event.who.to_string(),
-                    })),
-                    None => Ok(HttpResponse::BadRequest().json(RequestError {
-                        message: json!("Failed to find sugarfunge::asset::events::ClassCreated"),
-                    })),
-                }
+    let result = result
+        .find_first_event::<sugarfunge::asset::events::AssetCreated>()
+        .map_err(map_subxt_err)?;
 
-            } else {
-                Ok(HttpResponse::BadRequest().json(RequestError {
-                    message: json!("Not found user Attributes"),
-                }))
-            }
-        },
-        Err(_) => Ok(HttpResponse::BadRequest().json(RequestError {
-            message: json!("Failed to find user::getAttributes"),
-        }))
+    match result {
+        Some(event) => Ok(HttpResponse::Ok().json(CreateOutput {
+            class_id: event.class_id,
+            asset_id: event.asset_id,
+            who: event.who.to_string(),
+        })),
+        None => Ok(HttpResponse::BadRequest().json(RequestError {
+            message: json!("Failed to find sugarfunge::asset::events::ClassCreated"),
+        })),
     }
 }
 
@@ -167,55 +164,38 @@ pub struct MintOutput {
 pub async fn mint(
     data: web::Data<AppState>,
     req: web::Json<MintInput>,
+    query: web::Query<tx::WaitQuery>,
     claims: KeycloakClaims<user::ClaimsWithEmail>,
-    env: web::Data<Config>
 ) -> error::Result<HttpResponse> {
-
-    match user::get_seed(&claims.sub, env).await {
-        Ok(response) => {
-            if !response.seed.clone().unwrap_or_default().is_empty() {
-                let user_seed = response.seed.clone().unwrap();
-
-                let pair = get_pair_from_seed(&user_seed)?;
-                let signer = PairSigner::new(pair);
-                let to = sp_core::sr25519::Public::from_str(&req.to).map_err(map_account_err)?;
-                let to = sp_core::crypto::AccountId32::from(to);
-                let api = data.api.lock().unwrap();
-                let result = api
-                    .tx()
-                    .asset()
-                    .mint(to, req.class_id, req.asset_id, req.amount)
-                    .sign_and_submit_then_watch(&signer)
-                    .await
-                    .map_err(map_subxt_err)?
-                    .wait_for_finalized_success()
-                    .await
-                    .map_err(map_subxt_err)?;
-                let result = result
-                    .find_first_event::<sugarfunge::asset::events::Mint>()
-                    .map_err(map_subxt_err)?;
-                match result {
-                    Some(event) => Ok(HttpResponse::Ok().json(MintOutput {
-                        to: event.to.to_string(),
-                        class_id: event.class_id,
-                        asset_id: event.asset_id,
-                        amount: event.amount,
-                        who: event.who.to_string(),
-                    })),
-                    None => Ok(HttpResponse::BadRequest().json(RequestError {
-                        message: json!("Failed to find sugarfunge::currency::events::AssetMint"),
-                    })),
-                }
-
-            } else {
-                Ok(HttpResponse::BadRequest().json(RequestError {
-                    message: json!("Not found user Attributes"),
-                }))
-            }
-        },
-        Err(_) => Ok(HttpResponse::BadRequest().json(RequestError {
-            message: json!("Failed to find user::getAttributes"),
-        }))
+    let to = sp_core::sr25519::Public::from_str(&req.to).map_err(map_account_err)?;
+    let to = sp_core::crypto::AccountId32::from(to);
+    let signer = signer::pair_signer(&data, &claims.sub).await?;
+    let api = data.api.get();
+    let result = api
+        .tx()
+        .asset()
+        .mint(to, req.class_id, req.asset_id, req.amount)
+        .sign_and_submit_then_watch(&signer)
+        .await
+        .map_err(data.nonces.map_err(&signer))?;
+    let result = match tx::wait(&data, &claims.sub, &signer, result, query.wait).await? {
+        tx::Submitted::Done(events) => events,
+        tx::Submitted::Pending(job) => return Ok(HttpResponse::Accepted().json(job)),
+    };
+    let result = result
+        .find_first_event::<sugarfunge::asset::events::Mint>()
+        .map_err(map_subxt_err)?;
+    match result {
+        Some(event) => Ok(HttpResponse::Ok().json(MintOutput {
+            to: event.to.to_string(),
+            class_id: event.class_id,
+            asset_id: event.asset_id,
+            amount: event.amount,
+            who: event.who.to_string(),
+        })),
+        None => Ok(HttpResponse::BadRequest().json(RequestError {
+            message: json!("Failed to find sugarfunge::currency::events::AssetMint"),
+        })),
     }
 }
 
@@ -240,54 +220,38 @@ pub struct BurnOutput {
 pub async fn burn(
     data: web::Data<AppState>,
     req: web::Json<BurnInput>,
+    query: web::Query<tx::WaitQuery>,
     claims: KeycloakClaims<user::ClaimsWithEmail>,
-    env: web::Data<Config>
 ) -> error::Result<HttpResponse> {
-    match user::get_seed(&claims.sub, env).await {
-        Ok(response) => {
-            if !response.seed.clone().unwrap_or_default().is_empty() {
-                let user_seed = response.seed.clone().unwrap();
-
-                let pair = get_pair_from_seed(&user_seed)?;
-                let signer = PairSigner::new(pair);
-                let from = sp_core::sr25519::Public::from_str(&req.from).map_err(map_account_err)?;
-                let from = sp_core::crypto::AccountId32::from(from);
-                let api = data.api.lock().unwrap();
-                let result = api
-                    .tx()
-                    .asset()
-                    .burn(from, req.class_id, req.asset_id, req.amount)
-                    .sign_and_submit_then_watch(&signer)
-                    .await
-                    .map_err(map_subxt_err)?
-                    .wait_for_finalized_success()
-                    .await
-                    .map_err(map_subxt_err)?;
-                let result = result
-                    .find_first_event::<sugarfunge::asset::events::Burn>()
-                    .map_err(map_subxt_err)?;
-                match result {
-                    Some(event) => Ok(HttpResponse::Ok().json(BurnOutput {
-                        from: event.from.to_string(),
-                        class_id: event.class_id,
-                        asset_id: event.asset_id,
-                        amount: event.amount,
-                        who: event.who.to_string(),
-                    })),
-                    None => Ok(HttpResponse::BadRequest().json(RequestError {
-                        message: json!("Failed to find sugarfunge::currency::events::Burn"),
-                    })),
-                }
-
-            } else {
-                Ok(HttpResponse::BadRequest().json(RequestError {
-                    message: json!("Not found user Attributes"),
-                }))
-            }
-        },
-        Err(_) => Ok(HttpResponse::BadRequest().json(RequestError {
-            message: json!("Failed to find user::getAttributes"),
-        }))
+    let from = sp_core::sr25519::Public::from_str(&req.from).map_err(map_account_err)?;
+    let from = sp_core::crypto::AccountId32::from(from);
+    let signer = signer::pair_signer(&data, &claims.sub).await?;
+    let api = data.api.get();
+    let result = api
+        .tx()
+        .asset()
+        .burn(from, req.class_id, req.asset_id, req.amount)
+        .sign_and_submit_then_watch(&signer)
+        .await
+        .map_err(data.nonces.map_err(&signer))?;
+    let result = match tx::wait(&data, &claims.sub, &signer, result, query.wait).await? {
+        tx::Submitted::Done(events) => events,
+        tx::Submitted::Pending(job) => return Ok(HttpResponse::Accepted().json(job)),
+    };
+    let result = result
+        .find_first_event::<sugarfunge::asset::events::Burn>()
+        .map_err(map_subxt_err)?;
+    match result {
+        Some(event) => Ok(HttpResponse::Ok().json(BurnOutput {
+            from: event.from.to_string(),
+            class_id: event.class_id,
+            asset_id: event.asset_id,
+            amount: event.amount,
+            who: event.who.to_string(),
+        })),
+        None => Ok(HttpResponse::BadRequest().json(RequestError {
+            message: json!("Failed to find sugarfunge::currency::events::Burn"),
+        })),
     }
 }
 
@@ -310,7 +274,7 @@ pub async fn balance(
 ) -> error::Result<HttpResponse> {
     let account = sp_core::sr25519::Public::from_str(&req.account).map_err(map_account_err)?;
     let account = sp_core::crypto::AccountId32::from(account);
-    let api = data.api.lock().unwrap();
+    let api = data.api.get();
     let result = api
         .storage()
         .asset()
@@ -324,11 +288,14 @@ pub async fn balance(
 pub struct AssetBalancesInput {
     account: String,
     class_id: Option<u64>,
+    cursor: Option<String>,
+    limit: Option<u32>,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct AssetBalancesOutput {
     balances: Vec<AssetBalanceItemOutput>,
+    next_cursor: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -338,25 +305,55 @@ pub struct AssetBalanceItemOutput {
     amount: u128,
 }
 
+const BALANCES_LIMIT: u32 = 100;
+const BALANCES_MAX_LIMIT: u32 = 1000;
+
+/// Decode the (account, class_id, asset_id) of an `Asset::Balances` key
+fn decode_balance_key(key: &[u8]) -> Option<(sp_core::crypto::AccountId32, u64, u64)> {
+    let rest = key.get(32..)?;
+    let (account, rest) = storage::decode_blake2_128_concat(rest)?;
+    let (class_id, rest) = storage::decode_blake2_128_concat::<u64>(rest)?;
+    let (asset_id, _) = storage::decode_blake2_128_concat::<u64>(rest)?;
+    Some((account, class_id, asset_id))
+}
+
 /// Get balances for given account
-// pub async fn balances(
-//     data: web::Data<AppState>,
-//     req: web::Json<AssetBalancesInput>,
-// ) -> error::Result<HttpResponse> {
-//     let account =
-//         sp_core::sr25519::Public::from_str(&req.account).map_err(map_account_err)?;
-//     let account = sp_core::crypto::AccountId32::from(account);
-//     let api = data.api.lock().unwrap();
-//     // let result = api
-//     //     .storage()
-//     //     .asset()
-//     //     .balances(account, req.class_id, req.asset_id, None)
-//     //     .await;
-//     let result = api.storage().asset().balances_iter(None).await.map_err(map_subxt_err)?;
-//     result.next()
-//     let amount = result.map_err(map_subxt_err)?;
-//     Ok(HttpResponse::Ok().json(AssetBalancesOutput { amount }))
-// }
+pub async fn balances(
+    data: web::Data<AppState>,
+    req: web::Json<AssetBalancesInput>,
+) -> error::Result<HttpResponse> {
+    let account = sp_core::sr25519::Public::from_str(&req.account).map_err(map_account_err)?;
+    let account = sp_core::crypto::AccountId32::from(account);
+    let mut prefix = storage::storage_prefix("Asset", "Balances");
+    prefix.extend(storage::blake2_128_concat(&account));
+    if let Some(class_id) = req.class_id {
+        prefix.extend(storage::blake2_128_concat(&class_id));
+    }
+    let cursor = storage::parse_cursor(&req.cursor).map_err(map_cursor_err)?;
+    let limit = req.limit.unwrap_or(BALANCES_LIMIT).clamp(1, BALANCES_MAX_LIMIT);
+    let api = data.api.get();
+    let page = storage::entries_page(&api, &prefix, limit, cursor, None)
+        .await
+        .map_err(map_subxt_err)?;
+    let balances = page
+        .entries
+        .iter()
+        .filter_map(|(key, value)| {
+            let (_, class_id, asset_id) = decode_balance_key(key)?;
+            let amount = u128::decode(&mut &value[..]).ok()?;
+            Some(AssetBalanceItemOutput {
+                class_id,
+                asset_id,
+                amount,
+            })
+        })
+        .filter(|balance| balance.amount > 0)
+        .collect();
+    Ok(HttpResponse::Ok().json(AssetBalancesOutput {
+        balances,
+        next_cursor: page.next.as_deref().map(storage::format_cursor),
+    }))
+}
 
 #[derive(Serialize, Deserialize)]
 pub struct TransferFromInput {
@@ -381,63 +378,1173 @@ pub struct TransferFromOutput {
 pub async fn transfer_from(
     data: web::Data<AppState>,
     req: web::Json<TransferFromInput>,
+    query: web::Query<tx::WaitQuery>,
     claims: KeycloakClaims<user::ClaimsWithEmail>,
-    env: web::Data<Config>
 ) -> error::Result<HttpResponse> {
+    let account_from = sp_core::sr25519::Public::from_str(&req.from).map_err(map_account_err)?;
+    let account_to = sp_core::sr25519::Public::from_str(&req.to).map_err(map_account_err)?;
+    let account_from = sp_core::crypto::AccountId32::from(account_from);
+    let account_to = sp_core::crypto::AccountId32::from(account_to);
+    let pair = signer::user_pair(&data, &claims.sub).await?;
+    let who = sp_core::crypto::AccountId32::from(pair.public());
+    let api = data.api.get();
+    policy::check_transfer(&api, req.class_id, &who).await?;
+    let signer = signer::tx_signer(&data, pair).await?;
+    let result = api
+        .tx()
+        .asset()
+        .transfer_from(
+            account_from,
+            account_to,
+            req.class_id,
+            req.asset_id,
+            req.amount,
+        )
+        .sign_and_submit_then_watch(&signer)
+        .await
+        .map_err(data.nonces.map_err(&signer))?;
+    let result = match tx::wait(&data, &claims.sub, &signer, result, query.wait).await? {
+        tx::Submitted::Done(events) => events,
+        tx::Submitted::Pending(job) => return Ok(HttpResponse::Accepted().json(job)),
+    };
+    let result = result
+        .find_first_event::<sugarfunge::asset::events::Transferred>()
+        .map_err(map_subxt_err)?;
+    match result {
+        Some(event) => Ok(HttpResponse::Ok().json(TransferFromOutput {
+            from: event.from.to_string(),
+            to: event.to.to_string(),
+            class_id: event.class_id,
+            asset_id: event.asset_id,
+            amount: event.amount,
+            who: event.who.to_string(),
+        })),
+        None => Ok(HttpResponse::BadRequest().json(RequestError {
+            message: json!("Failed to find sugarfunge::asset::events::Transferred"),
+        })),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AssetHoldersInput {
+    class_id: u64,
+    asset_id: u64,
+    /// `next_cursor` of the previous page
+    cursor: Option<String>,
+    limit: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AssetHoldersOutput {
+    class_id: u64,
+    asset_id: u64,
+    /// Last block indexed, the holders are the ones at this block
+    indexed_block: Option<u32>,
+    holder_count: u64,
+    total: u128,
+    /// `true` when the balances were not indexed from the genesis block, holders
+    /// that got the asset before the first indexed block are missing
+    partial: bool,
+    holders: Vec<AssetHolderOutput>,
+    next_cursor: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AssetHolderOutput {
+    account: String,
+    amount: u128,
+}
+
+/// Cursor of the holders page after `holder`, its amount and account
+fn holders_cursor(holder: &AssetHolderOutput) -> String {
+    format!("{}:{}", holder.amount, holder.account)
+}
+
+fn parse_holders_cursor(cursor: &str) -> error::Result<(u128, String)> {
+    cursor
+        .split_once(':')
+        .and_then(|(amount, account)| Some((amount.parse().ok()?, account.to_string())))
+        .ok_or_else(|| {
+            let req_error = RequestError {
+                message: json!("Invalid cursor"),
+            };
+            let req_error = serde_json::to_string_pretty(&req_error).unwrap();
+            error::ErrorBadRequest(req_error)
+        })
+}
+
+/// Get the accounts holding an asset from the indexer, largest balances first,
+/// with the holder count and total held
+pub async fn holders(
+    data: web::Data<AppState>,
+    req: web::Json<AssetHoldersInput>,
+) -> error::Result<HttpResponse> {
+    let db = require_db(&data.db)?;
+    let after = req.cursor.as_deref().map(parse_holders_cursor).transpose()?;
+    let limit = req.limit.unwrap_or(BALANCES_LIMIT).clamp(1, BALANCES_MAX_LIMIT);
+    let page = db
+        .asset_holders(req.class_id, req.asset_id, after, limit)
+        .await
+        .map_err(map_db_err)?;
+    let holders: Vec<AssetHolderOutput> = page
+        .holders
+        .into_iter()
+        .map(|(account, amount)| AssetHolderOutput { account, amount })
+        .collect();
+    let next_cursor = match holders.last() {
+        Some(holder) if holders.len() == limit as usize => Some(holders_cursor(holder)),
+        _ => None,
+    };
+    Ok(HttpResponse::Ok().json(AssetHoldersOutput {
+        class_id: req.class_id,
+        asset_id: req.asset_id,
+        indexed_block: page.block_number,
+        holder_count: page.holder_count,
+        total: page.total,
+        partial: page.balances_start != Some(0),
+        holders,
+        next_cursor,
+    }))
+}
 
-    match user::get_seed(&claims.sub, env).await {
-        Ok(response) => {
-            if !response.seed.clone().unwrap_or_default().is_empty() {
-                let user_seed = response.seed.clone().unwrap();
-
-                let pair = get_pair_from_seed(&user_seed)?;
-                let signer = PairSigner::new(pair);
-                let account_from = sp_core::sr25519::Public::from_str(&req.from).map_err(map_account_err)?;
-                let account_to = sp_core::sr25519::Public::from_str(&req.to).map_err(map_account_err)?;
-                let account_from = sp_core::crypto::AccountId32::from(account_from);
-                let account_to = sp_core::crypto::AccountId32::from(account_to);
-                let api = data.api.lock().unwrap();
-                let result = api
-                    .tx()
-                    .asset()
-                    .transfer_from(
-                        account_from,
-                        account_to,
-                        req.class_id,
-                        req.asset_id,
-                        req.amount,
-                    )
-                    .sign_and_submit_then_watch(&signer)
-                    .await
-                    .map_err(map_subxt_err)?
-                    .wait_for_finalized_success()
-                    .await
-                    .map_err(map_subxt_err)?;
-                let result = result
-                    .find_first_event::<sugarfunge::asset::events::Transferred>()
-                    .map_err(map_subxt_err)?;
-                match result {
-                    Some(event) => Ok(HttpResponse::Ok().json(TransferFromOutput {
-                        from: event.from.to_string(),
-                        to: event.to.to_string(),
-                        class_id: event.class_id,
-                        asset_id: event.asset_id,
-                        amount: event.amount,
-                        who: event.who.to_string(),
-                    })),
-                    None => Ok(HttpResponse::BadRequest().json(RequestError {
-                        message: json!("Failed to find sugarfunge::asset::events::Transferred"),
-                    })),
-                }
+#[derive(Serialize, Deserialize)]
+pub struct AssetHoldersCheckInput {
+    class_id: u64,
+    asset_id: u64,
+}
 
-            } else {
-                Ok(HttpResponse::BadRequest().json(RequestError {
-                    message: json!("Not found user Attributes"),
-                }))
+#[derive(Serialize, Deserialize)]
+pub struct AssetHoldersCheckOutput {
+    class_id: u64,
+    asset_id: u64,
+    /// Last indexed block, the storage is scanned at this block
+    block_hash: String,
+    block_number: u32,
+    holder_count: u64,
+    total: u128,
+    indexed_holder_count: u64,
+    indexed_total: u128,
+    consistent: bool,
+    mismatches: Vec<HolderMismatchOutput>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct HolderMismatchOutput {
+    account: String,
+    amount: u128,
+    indexed_amount: u128,
+}
+
+/// Keys read per storage request when scanning a whole storage map
+const SCAN_PAGE: u32 = 1000;
+const HOLDERS_MAX_MISMATCHES: usize = 100;
+/// Least time between two holders consistency checks, each one scans the whole
+/// `Asset::Balances` map
+const HOLDERS_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Non-zero balances of an asset, from a scan of `Asset::Balances` at block `at`
+async fn storage_holders(
+    api: &RuntimeApi,
+    class_id: u64,
+    asset_id: u64,
+    at: sp_core::H256,
+) -> Result<BTreeMap<String, u128>, subxt::Error> {
+    let prefix = storage::storage_prefix("Asset", "Balances");
+    let mut holders = BTreeMap::new();
+    let mut start_key = None;
+    loop {
+        let page = storage::entries_page(api, &prefix, SCAN_PAGE, start_key, Some(at)).await?;
+        for (key, value) in &page.entries {
+            let (account, key_class_id, key_asset_id) = match decode_balance_key(key) {
+                Some(key) => key,
+                None => continue,
+            };
+            if key_class_id != class_id || key_asset_id != asset_id {
+                continue;
             }
-        },
-        Err(_) => Ok(HttpResponse::BadRequest().json(RequestError {
-            message: json!("Failed to find user::getAttributes"),
+            let amount = u128::decode(&mut &value[..]).unwrap_or_default();
+            if amount > 0 {
+                holders.insert(account.to_string(), amount);
+            }
+        }
+        match page.next {
+            Some(next) => start_key = Some(next),
+            None => break,
+        }
+    }
+    Ok(holders)
+}
+
+/// Compare the indexed holders of an asset with a scan of the chain storage at
+/// the last indexed block. One check runs at a time, at most once per
+/// `HOLDERS_CHECK_INTERVAL`.
+pub async fn holders_check(
+    data: web::Data<AppState>,
+    req: web::Json<AssetHoldersCheckInput>,
+) -> error::Result<HttpResponse> {
+    let db = require_db(&data.db)?;
+    let mut last_check = match data.holders_check.try_lock() {
+        Ok(last_check) => last_check,
+        Err(_) => {
+            return Ok(HttpResponse::TooManyRequests().json(RequestError {
+                message: json!("A holders check is already running"),
+            }))
+        }
+    };
+    if let Some(wait) = last_check.and_then(|last| HOLDERS_CHECK_INTERVAL.checked_sub(last.elapsed())) {
+        return Ok(HttpResponse::TooManyRequests()
+            .insert_header((header::RETRY_AFTER, wait.as_secs().max(1).to_string()))
+            .json(RequestError {
+                message: json!("A holders check ran recently, retry later"),
+            }));
+    }
+    *last_check = Some(std::time::Instant::now());
+
+    let indexed = match db
+        .all_asset_holders(req.class_id, req.asset_id)
+        .await
+        .map_err(map_db_err)?
+    {
+        Some(indexed) => indexed,
+        None => {
+            return Ok(HttpResponse::NotFound().json(RequestError {
+                message: json!("No block indexed yet"),
+            }))
+        }
+    };
+    let at = parse_block_hash(&indexed.block_hash)?;
+    let api = data.api.get();
+    let holders = storage_holders(&api, req.class_id, req.asset_id, at)
+        .await
+        .map_err(map_subxt_err)?;
+    let indexed_holders: BTreeMap<String, u128> = indexed.holders.into_iter().collect();
+
+    let mismatches: Vec<HolderMismatchOutput> = holders
+        .keys()
+        .chain(indexed_holders.keys())
+        .collect::<std::collections::BTreeSet<_>>()
+        .into_iter()
+        .filter_map(|account| {
+            let amount = holders.get(account).copied().unwrap_or_default();
+            let indexed_amount = indexed_holders.get(account).copied().unwrap_or_default();
+            (amount != indexed_amount).then(|| HolderMismatchOutput {
+                account: account.clone(),
+                amount,
+                indexed_amount,
+            })
+        })
+        .collect();
+    let sum = |holders: &BTreeMap<String, u128>| {
+        holders
+            .values()
+            .fold(0u128, |total, amount| total.saturating_add(*amount))
+    };
+    Ok(HttpResponse::Ok().json(AssetHoldersCheckOutput {
+        class_id: req.class_id,
+        asset_id: req.asset_id,
+        block_hash: indexed.block_hash,
+        block_number: indexed.block_number,
+        holder_count: holders.len() as u64,
+        total: sum(&holders),
+        indexed_holder_count: indexed_holders.len() as u64,
+        indexed_total: sum(&indexed_holders),
+        consistent: mismatches.is_empty(),
+        mismatches: mismatches.into_iter().take(HOLDERS_MAX_MISMATCHES).collect(),
+    }))
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SnapshotFormat {
+    Csv,
+    Ndjson,
+}
+
+impl Default for SnapshotFormat {
+    fn default() -> Self {
+        SnapshotFormat::Csv
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AssetSnapshotInput {
+    /// Block hash to read the balances at, defaults to the finalized head
+    at: Option<String>,
+    class_id: Option<u64>,
+    #[serde(default)]
+    format: SnapshotFormat,
+}
+
+#[derive(Serialize)]
+struct SnapshotRow {
+    source: &'static str,
+    account: String,
+    class_id: u64,
+    asset_id: u64,
+    amount: u128,
+}
+
+impl SnapshotRow {
+    fn write(&self, format: SnapshotFormat, out: &mut Vec<u8>) {
+        match format {
+            SnapshotFormat::Csv => out.extend(
+                format!(
+                    "{},{},{},{},{}\n",
+                    self.source, self.account, self.class_id, self.asset_id, self.amount
+                )
+                .into_bytes(),
+            ),
+            SnapshotFormat::Ndjson => {
+                out.extend(serde_json::to_vec(self).unwrap_or_default());
+                out.push(b'\n');
+            }
+        }
+    }
+}
+
+/// Last line of a complete snapshot, with the number of balance rows before it.
+/// A snapshot that fails midway ends without it.
+fn snapshot_trailer(format: SnapshotFormat, rows: u64) -> Vec<u8> {
+    match format {
+        SnapshotFormat::Csv => format!("end,,,,{}\n", rows).into_bytes(),
+        SnapshotFormat::Ndjson => {
+            let mut out = serde_json::to_vec(&json!({ "source": "end", "rows": rows })).unwrap_or_default();
+            out.push(b'\n');
+            out
+        }
+    }
+}
+
+/// Storage maps read by `asset/snapshot`, in output order
+#[derive(Clone, Copy)]
+enum SnapshotSource {
+    Asset,
+    Currency,
+}
+
+impl SnapshotSource {
+    fn prefix(&self) -> Vec<u8> {
+        match self {
+            SnapshotSource::Asset => storage::storage_prefix("Asset", "Balances"),
+            SnapshotSource::Currency => storage::storage_prefix("OrmlTokens", "Accounts"),
+        }
+    }
+
+    fn row(&self, key: &[u8], value: &[u8]) -> Option<SnapshotRow> {
+        match self {
+            SnapshotSource::Asset => {
+                let (account, class_id, asset_id) = decode_balance_key(key)?;
+                let amount = u128::decode(&mut &value[..]).ok()?;
+                Some(SnapshotRow {
+                    source: "asset",
+                    account: account.to_string(),
+                    class_id,
+                    asset_id,
+                    amount,
+                })
+            }
+            SnapshotSource::Currency => {
+                let rest = key.get(32..)?;
+                let (account, rest) =
+                    storage::decode_blake2_128_concat::<sp_core::crypto::AccountId32>(rest)?;
+                let (currency_id, _) = storage::decode_twox_64_concat::<(u64, u64)>(rest)?;
+                let (free, reserved, _frozen) = <(u128, u128, u128)>::decode(&mut &value[..]).ok()?;
+                Some(SnapshotRow {
+                    source: "currency",
+                    account: account.to_string(),
+                    class_id: currency_id.0,
+                    asset_id: currency_id.1,
+                    amount: free.saturating_add(reserved),
+                })
+            }
+        }
+    }
+}
+
+struct SnapshotState {
+    api: ClientAPI,
+    sources: Vec<SnapshotSource>,
+    start_key: Option<Vec<u8>>,
+    /// Balance rows written so far
+    rows: u64,
+    /// Set once the trailer is written or the snapshot failed
+    done: bool,
+}
+
+/// Stream every non-zero `Asset::Balances` and `OrmlTokens::Accounts` balance at
+/// a block as CSV or NDJSON, followed by a trailer with the row count. Currency
+/// amounts are free plus reserved.
+pub async fn snapshot(
+    data: web::Data<AppState>,
+    req: web::Json<AssetSnapshotInput>,
+) -> error::Result<HttpResponse> {
+    let api = data.api.get();
+    let at = match &req.at {
+        Some(at) => parse_block_hash(at)?,
+        None => api.client.rpc().finalized_head().await.map_err(map_subxt_err)?,
+    };
+    let header = api.client.rpc().header(Some(at)).await.map_err(map_subxt_err)?;
+    if header.is_none() {
+        return Ok(HttpResponse::NotFound().json(RequestError {
+            message: json!("Block not found"),
+        }));
+    }
+    let class_id = req.class_id;
+    let format = req.format;
+    let state = SnapshotState {
+        api,
+        sources: vec![SnapshotSource::Asset, SnapshotSource::Currency],
+        start_key: None,
+        rows: 0,
+        done: false,
+    };
+    let rows = futures::stream::unfold(state, move |mut state| async move {
+        loop {
+            let source = match state.sources.first() {
+                Some(source) => *source,
+                None if state.done => return None,
+                None => {
+                    state.done = true;
+                    let trailer = snapshot_trailer(format, state.rows);
+                    return Some((Ok(web::Bytes::from(trailer)), state));
+                }
+            };
+            let page = storage::entries_page(
+                &state.api,
+                &source.prefix(),
+                SCAN_PAGE,
+                state.start_key.take(),
+                Some(at),
+            )
+            .await;
+            let page = match page {
+                Ok(page) => page,
+                Err(e) => {
+                    log::error!("Snapshot at {:?} failed: {}", at, e);
+                    state.sources.clear();
+                    state.done = true;
+                    return Some((Err(e), state));
+                }
+            };
+            let mut out = vec![];
+            page.entries
+                .iter()
+                .filter_map(|(key, value)| source.row(key, value))
+                .filter(|row| row.amount > 0 && class_id.map_or(true, |class_id| class_id == row.class_id))
+                .for_each(|row| {
+                    row.write(format, &mut out);
+                    state.rows += 1;
+                });
+            match page.next {
+                Some(next) => state.start_key = Some(next),
+                None => {
+                    state.sources.remove(0);
+                }
+            }
+            if !out.is_empty() {
+                return Some((Ok(web::Bytes::from(out)), state));
+            }
+        }
+    });
+    let response = match format {
+        SnapshotFormat::Csv => {
+            let columns = futures::stream::once(futures::future::ready(Ok(web::Bytes::from_static(
+                b"source,account,class_id,asset_id,amount\n",
+            ))));
+            HttpResponse::Ok()
+                .content_type("text/csv")
+                .insert_header(("Snapshot-Block-Hash", format!("{:?}", at)))
+                .streaming(columns.chain(rows))
+        }
+        SnapshotFormat::Ndjson => HttpResponse::Ok()
+            .content_type("application/x-ndjson")
+            .insert_header(("Snapshot-Block-Hash", format!("{:?}", at)))
+            .streaming(rows),
+    };
+    Ok(response)
+}
+
+/// Decode metadata stored on chain, as JSON when it is JSON or hex otherwise
+pub fn decode_metadata(metadata: &[u8]) -> serde_json::Value {
+    serde_json::from_slice(metadata).unwrap_or_else(|_| json!(format!("0x{}", hex::encode(metadata))))
+}
+
+/// Asset metadata as written on chain, or the stored document when `offchain` is set
+async fn asset_metadata_bytes(
+    data: &AppState,
+    metadata: &serde_json::Value,
+    offchain: bool,
+) -> error::Result<Vec<u8>> {
+    let metadata = serde_json::to_vec(metadata).unwrap_or_default();
+    if !offchain {
+        return Ok(metadata);
+    }
+    let store = blob::require_blobs(&data.blobs)?;
+    let pointer = store.put(metadata).await.map_err(blob::map_blob_err)?;
+    Ok(serde_json::to_vec(&pointer).unwrap_or_default())
+}
+
+/// Decode asset metadata, resolving off-chain documents from the blob store
+async fn asset_metadata(data: &AppState, metadata: &[u8]) -> serde_json::Value {
+    blob::resolve(data.blobs.as_deref(), decode_metadata(metadata)).await
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct IssuanceOutput {
+    minted: u128,
+    burned: u128,
+    supply: u128,
+    /// The indexer did not start at genesis, mints and burns of earlier blocks are missing
+    partial: bool,
+}
+
+/// Minted and burned amounts of a class, or of one of its assets, from the totals kept by the indexer
+async fn indexed_issuance(
+    db: &Database,
+    class_id: u64,
+    asset_id: Option<u64>,
+) -> Result<IssuanceOutput, sqlx::Error> {
+    let (minted, burned) = db.asset_issuance(class_id, asset_id).await?;
+    let partial = db.first_indexed_block().await? != Some(0);
+    Ok(IssuanceOutput {
+        minted,
+        burned,
+        supply: minted.saturating_sub(burned),
+        partial,
+    })
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ClassInfoInput {
+    class_id: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ClassInfoOutput {
+    class_id: u64,
+    owner: String,
+    metadata: serde_json::Value,
+    asset_count: u64,
+    issuance: Option<IssuanceOutput>,
+}
+
+/// Get owner, metadata and issuance of an asset class
+pub async fn class_info(
+    data: web::Data<AppState>,
+    req: web::Json<ClassInfoInput>,
+) -> error::Result<HttpResponse> {
+    let api = data.api.get();
+    let class = api
+        .storage()
+        .asset()
+        .classes(req.class_id, None)
+        .await
+        .map_err(map_subxt_err)?;
+    let class = match class {
+        Some(class) => class,
+        None => {
+            return Ok(HttpResponse::NotFound().json(RequestError {
+                message: json!("Class not found"),
+            }))
+        }
+    };
+    let asset_count = api
+        .storage()
+        .asset()
+        .asset_count(req.class_id, None)
+        .await
+        .map_err(map_subxt_err)?;
+    let issuance = match &data.db {
+        Some(db) => Some(
+            indexed_issuance(db, req.class_id, None)
+                .await
+                .map_err(map_db_err)?,
+        ),
+        None => None,
+    };
+    Ok(HttpResponse::Ok().json(ClassInfoOutput {
+        class_id: req.class_id,
+        owner: class.owner.to_string(),
+        metadata: decode_metadata(&class.metadata),
+        asset_count,
+        issuance,
+    }))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AssetInfoInput {
+    class_id: u64,
+    asset_id: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AssetInfoOutput {
+    class_id: u64,
+    asset_id: u64,
+    creator: String,
+    metadata: serde_json::Value,
+    issuance: Option<IssuanceOutput>,
+}
+
+/// Get creator, metadata and issuance of an asset
+pub async fn info(
+    data: web::Data<AppState>,
+    req: web::Json<AssetInfoInput>,
+) -> error::Result<HttpResponse> {
+    let api = data.api.get();
+    let asset = api
+        .storage()
+        .asset()
+        .assets(req.class_id, req.asset_id, None)
+        .await
+        .map_err(map_subxt_err)?;
+    let asset = match asset {
+        Some(asset) => asset,
+        None => {
+            return Ok(HttpResponse::NotFound().json(RequestError {
+                message: json!("Asset not found"),
+            }))
+        }
+    };
+    let issuance = match &data.db {
+        Some(db) => Some(
+            indexed_issuance(db, req.class_id, Some(req.asset_id))
+                .await
+                .map_err(map_db_err)?,
+        ),
+        None => None,
+    };
+    Ok(HttpResponse::Ok().json(AssetInfoOutput {
+        class_id: req.class_id,
+        asset_id: req.asset_id,
+        creator: asset.creator.to_string(),
+        metadata: asset_metadata(&data, &asset.metadata).await,
+        issuance,
+    }))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ClassesInput {
+    owner: Option<String>,
+    cursor: Option<String>,
+    limit: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ClassItemOutput {
+    class_id: u64,
+    owner: String,
+    metadata: serde_json::Value,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ClassesOutput {
+    classes: Vec<ClassItemOutput>,
+    next_cursor: Option<String>,
+}
+
+/// List asset classes, optionally only those of an owner
+pub async fn classes(
+    data: web::Data<AppState>,
+    req: web::Json<ClassesInput>,
+) -> error::Result<HttpResponse> {
+    let owner = match &req.owner {
+        Some(owner) => {
+            let owner = sp_core::sr25519::Public::from_str(owner).map_err(map_account_err)?;
+            Some(sp_core::crypto::AccountId32::from(owner))
+        }
+        None => None,
+    };
+    let cursor = storage::parse_cursor(&req.cursor).map_err(map_cursor_err)?;
+    let limit = req.limit.unwrap_or(BALANCES_LIMIT).clamp(1, BALANCES_MAX_LIMIT);
+    let prefix = storage::storage_prefix("Asset", "Classes");
+    let api = data.api.get();
+    let page = storage::entries_page(&api, &prefix, limit, cursor, None)
+        .await
+        .map_err(map_subxt_err)?;
+    let classes = page
+        .entries
+        .iter()
+        .filter_map(|(key, value)| {
+            let (class_id, _) = storage::decode_blake2_128_concat::<u64>(key.get(32..)?)?;
+            let class = Class::<sp_core::crypto::AccountId32>::decode(&mut &value[..]).ok()?;
+            Some((class_id, class))
+        })
+        .filter(|(_, class)| owner.as_ref().map_or(true, |owner| *owner == class.owner))
+        .map(|(class_id, class)| ClassItemOutput {
+            class_id,
+            owner: class.owner.to_string(),
+            metadata: decode_metadata(&class.metadata),
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(ClassesOutput {
+        classes,
+        next_cursor: page.next.as_deref().map(storage::format_cursor),
+    }))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UpdateClassMetadataInput {
+    class_id: u64,
+    metadata: serde_json::Value,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UpdateClassMetadataOutput {
+    class_id: u64,
+    old_metadata: serde_json::Value,
+    new_metadata: serde_json::Value,
+}
+
+fn class_owner_err() -> HttpResponse {
+    HttpResponse::Forbidden().json(RequestError {
+        message: json!("Signer is not the class owner"),
+    })
+}
+
+/// Update the metadata of a class owned by the signer
+pub async fn update_class_metadata(
+    data: web::Data<AppState>,
+    req: web::Json<UpdateClassMetadataInput>,
+    query: web::Query<tx::WaitQuery>,
+    claims: KeycloakClaims<user::ClaimsWithEmail>,
+) -> error::Result<HttpResponse> {
+    let pair = signer::user_pair(&data, &claims.sub).await?;
+    let who = sp_core::crypto::AccountId32::from(pair.public());
+    let api = data.api.get();
+    let class = api
+        .storage()
+        .asset()
+        .classes(req.class_id, None)
+        .await
+        .map_err(map_subxt_err)?;
+    let class = match class {
+        Some(class) if class.owner == who => class,
+        Some(_) => return Ok(class_owner_err()),
+        None => {
+            return Ok(HttpResponse::NotFound().json(RequestError {
+                message: json!("Class not found"),
+            }))
+        }
+    };
+    let checked = schema::check_class_metadata(&req.metadata)
+        .and_then(|_| policy::check_class_metadata(&req.metadata));
+    if let Err(response) = checked {
+        return Ok(response);
+    }
+    let metadata: Vec<u8> = serde_json::to_vec(&req.metadata).unwrap_or_default();
+    let signer = signer::tx_signer(&data, pair).await?;
+    let result = api
+        .tx()
+        .asset()
+        .update_class_metadata(req.class_id, metadata)
+        .sign_and_submit_then_watch(&signer)
+        .await
+        .map_err(data.nonces.map_err(&signer))?;
+    if let tx::Submitted::Pending(job) = tx::wait(&data, &claims.sub, &signer, result, query.wait).await? {
+        return Ok(HttpResponse::Accepted().json(job));
+    }
+    let updated = api
+        .storage()
+        .asset()
+        .classes(req.class_id, None)
+        .await
+        .map_err(map_subxt_err)?;
+    Ok(HttpResponse::Ok().json(UpdateClassMetadataOutput {
+        class_id: req.class_id,
+        old_metadata: decode_metadata(&class.metadata),
+        new_metadata: updated
+            .map(|class| decode_metadata(&class.metadata))
+            .unwrap_or(serde_json::Value::Null),
+    }))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UpdateMetadataInput {
+    class_id: u64,
+    asset_id: u64,
+    metadata: serde_json::Value,
+    #[serde(default)]
+    offchain: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UpdateMetadataOutput {
+    class_id: u64,
+    asset_id: u64,
+    old_metadata: serde_json::Value,
+    new_metadata: serde_json::Value,
+}
+
+/// Update the metadata of an asset of a class owned by the signer
+pub async fn update_metadata(
+    data: web::Data<AppState>,
+    req: web::Json<UpdateMetadataInput>,
+    query: web::Query<tx::WaitQuery>,
+    claims: KeycloakClaims<user::ClaimsWithEmail>,
+) -> error::Result<HttpResponse> {
+    let pair = signer::user_pair(&data, &claims.sub).await?;
+    let who = sp_core::crypto::AccountId32::from(pair.public());
+    let api = data.api.get();
+    let class = api
+        .storage()
+        .asset()
+        .classes(req.class_id, None)
+        .await
+        .map_err(map_subxt_err)?;
+    let class = match class {
+        Some(class) if class.owner == who => class,
+        Some(_) => return Ok(class_owner_err()),
+        None => {
+            return Ok(HttpResponse::NotFound().json(RequestError {
+                message: json!("Class not found"),
+            }))
+        }
+    };
+    let class_metadata = decode_metadata(&class.metadata);
+    if let Err(response) = schema::validate_asset_metadata(&class_metadata, &req.metadata) {
+        return Ok(response);
+    }
+    let asset = api
+        .storage()
+        .asset()
+        .assets(req.class_id, req.asset_id, None)
+        .await
+        .map_err(map_subxt_err)?;
+    let asset = match asset {
+        Some(asset) => asset,
+        None => {
+            return Ok(HttpResponse::NotFound().json(RequestError {
+                message: json!("Asset not found"),
+            }))
+        }
+    };
+    let metadata = asset_metadata_bytes(&data, &req.metadata, req.offchain).await?;
+    let signer = signer::tx_signer(&data, pair).await?;
+    let result = api
+        .tx()
+        .asset()
+        .update_asset_metadata(req.class_id, req.asset_id, metadata)
+        .sign_and_submit_then_watch(&signer)
+        .await
+        .map_err(data.nonces.map_err(&signer))?;
+    if let tx::Submitted::Pending(job) = tx::wait(&data, &claims.sub, &signer, result, query.wait).await? {
+        return Ok(HttpResponse::Accepted().json(job));
+    }
+    let updated = api
+        .storage()
+        .asset()
+        .assets(req.class_id, req.asset_id, None)
+        .await
+        .map_err(map_subxt_err)?;
+    let new_metadata = match updated {
+        Some(updated) => asset_metadata(&data, &updated.metadata).await,
+        None => serde_json::Value::Null,
+    };
+    Ok(HttpResponse::Ok().json(UpdateMetadataOutput {
+        class_id: req.class_id,
+        asset_id: req.asset_id,
+        old_metadata: asset_metadata(&data, &asset.metadata).await,
+        new_metadata,
+    }))
+}
+
+fn batch_size_err() -> HttpResponse {
+    HttpResponse::BadRequest().json(RequestError {
+        message: json!(format!("A batch must have between 1 and {} items", tx::BATCH_MAX_ITEMS)),
+    })
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BatchMintInput {
+    items: Vec<MintInput>,
+}
+
+#[derive(Serialize)]
+pub struct BatchMintOutput {
+    results: Vec<tx::BatchItemOutput<MintOutput>>,
+}
+
+/// Mint assets to many accounts, one transaction per item
+pub async fn batch_mint(
+    data: web::Data<AppState>,
+    req: web::Json<BatchMintInput>,
+    query: web::Query<tx::WaitQuery>,
+    claims: KeycloakClaims<user::ClaimsWithEmail>,
+) -> error::Result<HttpResponse> {
+    if req.items.is_empty() || req.items.len() > tx::BATCH_MAX_ITEMS {
+        return Ok(batch_size_err());
+    }
+    let items = req
+        .items
+        .iter()
+        .map(|item| {
+            let to = sp_core::sr25519::Public::from_str(&item.to).map_err(map_account_err)?;
+            let to = sp_core::crypto::AccountId32::from(to);
+            Ok((to, item.class_id, item.asset_id, item.amount))
+        })
+        .collect::<error::Result<Vec<_>>>()?;
+    let pair = signer::user_pair(&data, &claims.sub).await?;
+    let api = data.api.get();
+    let outcomes = tx::submit_batch(&data, &claims.sub, api, &pair, items, query.wait, |api, signer, item| async move {
+        let (to, class_id, asset_id, amount) = item;
+        let progress = api
+            .tx()
+            .asset()
+            .mint(to, class_id, asset_id, amount)
+            .sign_and_submit_then_watch(&signer)
+            .await;
+        (signer, progress)
+    })
+    .await;
+    let results = tx::batch_output(outcomes, "sugarfunge::asset::events::Mint", |events| {
+        let event = events.find_first_event::<sugarfunge::asset::events::Mint>()?;
+        Ok(event.map(|event| MintOutput {
+            to: event.to.to_string(),
+            class_id: event.class_id,
+            asset_id: event.asset_id,
+            amount: event.amount,
+            who: event.who.to_string(),
         }))
+    });
+    Ok(tx::batch_response(query.wait).json(BatchMintOutput { results }))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BatchTransferFromInput {
+    items: Vec<TransferFromInput>,
+}
+
+#[derive(Serialize)]
+pub struct BatchTransferFromOutput {
+    results: Vec<tx::BatchItemOutput<TransferFromOutput>>,
+}
+
+/// Transfer assets between many accounts, one transaction per item
+pub async fn batch_transfer_from(
+    data: web::Data<AppState>,
+    req: web::Json<BatchTransferFromInput>,
+    query: web::Query<tx::WaitQuery>,
+    claims: KeycloakClaims<user::ClaimsWithEmail>,
+) -> error::Result<HttpResponse> {
+    if req.items.is_empty() || req.items.len() > tx::BATCH_MAX_ITEMS {
+        return Ok(batch_size_err());
     }
+    let items = req
+        .items
+        .iter()
+        .map(|item| {
+            let from = sp_core::sr25519::Public::from_str(&item.from).map_err(map_account_err)?;
+            let to = sp_core::sr25519::Public::from_str(&item.to).map_err(map_account_err)?;
+            let from = sp_core::crypto::AccountId32::from(from);
+            let to = sp_core::crypto::AccountId32::from(to);
+            Ok((from, to, item.class_id, item.asset_id, item.amount))
+        })
+        .collect::<error::Result<Vec<_>>>()?;
+    let pair = signer::user_pair(&data, &claims.sub).await?;
+    let who = sp_core::crypto::AccountId32::from(pair.public());
+    let class_ids: std::collections::BTreeSet<u64> = req.items.iter().map(|item| item.class_id).collect();
+    let api = data.api.get();
+    for class_id in class_ids {
+        policy::check_transfer(&api, class_id, &who).await?;
+    }
+    let outcomes = tx::submit_batch(&data, &claims.sub, api, &pair, items, query.wait, |api, signer, item| async move {
+        let (from, to, class_id, asset_id, amount) = item;
+        let progress = api
+            .tx()
+            .asset()
+            .transfer_from(from, to, class_id, asset_id, amount)
+            .sign_and_submit_then_watch(&signer)
+            .await;
+        (signer, progress)
+    })
+    .await;
+    let results = tx::batch_output(outcomes, "sugarfunge::asset::events::Transferred", |events| {
+        let event = events.find_first_event::<sugarfunge::asset::events::Transferred>()?;
+        Ok(event.map(|event| TransferFromOutput {
+            from: event.from.to_string(),
+            to: event.to.to_string(),
+            class_id: event.class_id,
+            asset_id: event.asset_id,
+            amount: event.amount,
+            who: event.who.to_string(),
+        }))
+    });
+    Ok(tx::batch_response(query.wait).json(BatchTransferFromOutput { results }))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct IsApprovedForAllInput {
+    account: String,
+    operator: String,
+    class_id: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct IsApprovedForAllOutput {
+    account: String,
+    operator: String,
+    class_id: u64,
+    approved: bool,
+}
+
+#[derive(Deserialize)]
+struct OperatorApprovalData {
+    who: String,
+    operator: String,
+    approved: bool,
+}
+
+/// Whether an operator is approved for all the assets of a class of an account,
+/// from the last indexed `OperatorApprovalForAll` event
+pub async fn is_approved_for_all(
+    data: web::Data<AppState>,
+    req: web::Json<IsApprovedForAllInput>,
+) -> error::Result<HttpResponse> {
+    let db = require_db(&data.db)?;
+    let account = sp_core::sr25519::Public::from_str(&req.account).map_err(map_account_err)?;
+    let account = sp_core::crypto::AccountId32::from(account).to_string();
+    let operator = sp_core::sr25519::Public::from_str(&req.operator).map_err(map_account_err)?;
+    let operator = sp_core::crypto::AccountId32::from(operator).to_string();
+    let mut filter = EventFilter {
+        account: Some(account.clone()),
+        pallet: Some("Asset".to_string()),
+        variant: Some("OperatorApprovalForAll".to_string()),
+        class_id: Some(req.class_id),
+        limit: 100,
+        ..Default::default()
+    };
+    let approved = loop {
+        let rows = db.events(&filter).await.map_err(map_db_err)?;
+        let approval = rows
+            .iter()
+            .filter_map(|row| serde_json::from_str::<OperatorApprovalData>(&row.data).ok())
+            .find(|approval| approval.who == account && approval.operator == operator);
+        if let Some(approval) = approval {
+            break approval.approved;
+        }
+        match rows.last() {
+            Some(row) if rows.len() == filter.limit as usize => filter.before = Some(row.id),
+            _ => break false,
+        }
+    };
+    Ok(HttpResponse::Ok().json(IsApprovedForAllOutput {
+        account,
+        operator,
+        class_id: req.class_id,
+        approved,
+    }))
+}
+
+/// Token metadata in the ERC-1155 metadata JSON schema
+#[derive(Serialize, Deserialize)]
+pub struct Erc1155Metadata {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    image: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    decimals: Option<u64>,
+    properties: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Map the metadata written by `asset/create` to the ERC-1155 schema, fields
+/// other than name, description, image and decimals become properties
+fn erc1155_metadata(metadata: serde_json::Value) -> Erc1155Metadata {
+    let mut fields = match metadata {
+        serde_json::Value::Object(fields) => fields,
+        value => {
+            let mut fields = serde_json::Map::new();
+            fields.insert("value".to_string(), value);
+            fields
+        }
+    };
+    let mut string = |key: &str| match fields.remove(key) {
+        Some(serde_json::Value::String(value)) => Some(value),
+        Some(value) => {
+            fields.insert(key.to_string(), value);
+            None
+        }
+        None => None,
+    };
+    let name = string("name");
+    let description = string("description");
+    let image = string("image").or_else(|| string("image_url"));
+    let decimals = fields.get("decimals").and_then(|decimals| decimals.as_u64());
+    if decimals.is_some() {
+        fields.remove("decimals");
+    }
+    let properties = match fields.remove("properties") {
+        Some(serde_json::Value::Object(mut properties)) => {
+            for (key, value) in fields {
+                properties.entry(key).or_insert(value);
+            }
+            properties
+        }
+        Some(value) => {
+            fields.insert("properties".to_string(), value);
+            fields
+        }
+        None => fields,
+    };
+    Erc1155Metadata {
+        name,
+        description,
+        image,
+        decimals,
+        properties,
+    }
+}
+
+/// Serve the metadata of an asset as an ERC-1155 metadata JSON document.
+///
+/// It is read at the finalized head and the ETag is the hash of that block. A
+/// request with the ETag of an older block gets `304 Not Modified` as long as
+/// the metadata did not change since.
+pub async fn metadata_json(
+    data: web::Data<AppState>,
+    path: web::Path<(u64, u64)>,
+    req: HttpRequest,
+) -> error::Result<HttpResponse> {
+    let (class_id, asset_id) = path.into_inner();
+    let api = data.api.get();
+    let hash = api.client.rpc().finalized_head().await.map_err(map_subxt_err)?;
+    let asset = api
+        .storage()
+        .asset()
+        .assets(class_id, asset_id, Some(hash))
+        .await
+        .map_err(map_subxt_err)?;
+    let asset = match asset {
+        Some(asset) => asset,
+        None => {
+            return Ok(HttpResponse::NotFound().json(RequestError {
+                message: json!("Asset not found"),
+            }))
+        }
+    };
+
+    let cached = req
+        .headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|etag| etag.to_str().ok())
+        .and_then(|etag| sp_core::H256::from_str(etag.trim_start_matches("W/").trim_matches('"')).ok());
+    if let Some(cached) = cached {
+        let cached_asset = api.storage().asset().assets(class_id, asset_id, Some(cached)).await;
+        if let Ok(Some(cached_asset)) = cached_asset {
+            if cached_asset.metadata == asset.metadata {
+                return Ok(HttpResponse::NotModified()
+                    .insert_header((header::ETAG, format!("\"{:?}\"", cached)))
+                    .finish());
+            }
+        }
+    }
+
+    let metadata = asset_metadata(&data, &asset.metadata).await;
+    Ok(HttpResponse::Ok()
+        .insert_header((header::ETAG, format!("\"{:?}\"", hash)))
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        .json(erc1155_metadata(metadata)))
 }
@@ -0,0 +1,151 @@
+use crate::db::{map_db_err, AuditRecord, Database};
+use crate::state::*;
+use crate::sugarfunge;
+use crate::tx;
+use crate::util::*;
+use actix_web::{error, HttpResponse};
+use serde::Serialize;
+use serde_json::json;
+
+/// Entry of the sudo audit log. Entries are logged under the `audit` target
+/// and written to the `sudo_audit` table when a database is configured.
+#[derive(Clone)]
+pub struct SudoAudit {
+    db: Option<Database>,
+    id: Option<i64>,
+    sub: String,
+    call: String,
+}
+
+impl SudoAudit {
+    /// Record a sudo call before it is signed, fails the request when the
+    /// entry cannot be written
+    pub async fn start<T: Serialize>(
+        data: &AppState,
+        sub: &str,
+        account: &str,
+        call: &str,
+        args: &T,
+    ) -> error::Result<Self> {
+        let args = serde_json::to_string(args).unwrap_or_default();
+        log::info!(target: "audit", "{} ({}) submits sudo {} {}", sub, account, call, args);
+        let id = match &data.db {
+            Some(db) => {
+                let record = AuditRecord {
+                    sub: sub.to_string(),
+                    account: account.to_string(),
+                    call: call.to_string(),
+                    args,
+                };
+                Some(db.insert_audit(&record).await.map_err(map_db_err)?)
+            }
+            None => None,
+        };
+        Ok(SudoAudit {
+            db: data.db.clone(),
+            id,
+            sub: sub.to_string(),
+            call: call.to_string(),
+        })
+    }
+
+    pub async fn finish(&self, status: &str, tx_hash: Option<&str>, error: Option<&str>) {
+        log::info!(
+            target: "audit",
+            "sudo {} {} {}",
+            self.call,
+            status,
+            error.or(tx_hash).unwrap_or_default()
+        );
+        if let (Some(db), Some(id)) = (&self.db, self.id) {
+            if let Err(e) = db.update_audit(id, status, tx_hash, error).await {
+                log::error!("Failed to update sudo audit entry {}: {}", id, e);
+            }
+        }
+    }
+
+    /// Record the outcome of a sudo call submitted with `wait=none`
+    async fn finish_job(&self, outcome: tx::JobOutcome, tx_hash: &str) {
+        match outcome {
+            tx::JobOutcome::Done(events) => match dispatch_result(&events) {
+                Ok(Ok(())) => self.finish("succeeded", Some(tx_hash), None).await,
+                Ok(Err(message)) => self.finish("dispatch_failed", Some(tx_hash), Some(&message)).await,
+                Err(e) => self.finish("failed", Some(tx_hash), Some(&e.to_string())).await,
+            },
+            tx::JobOutcome::Failed(message) => self.finish("rejected", Some(tx_hash), Some(&message)).await,
+        }
+    }
+}
+
+/// Result of the call wrapped by a sudo transaction, from its `Sudid` or
+/// `SudoAsDone` event
+fn dispatch_result(events: &TxEvents) -> Result<Result<(), String>, subxt::Error> {
+    let sudid = events
+        .find_first_event::<sugarfunge::sudo::events::Sudid>()?
+        .map(|event| event.sudo_result);
+    let sudo_as_done = events
+        .find_first_event::<sugarfunge::sudo::events::SudoAsDone>()?
+        .map(|event| event.sudo_result);
+    Ok(match sudid.or(sudo_as_done) {
+        Some(Err(e)) => Err(format!("{:?}", e)),
+        _ => Ok(()),
+    })
+}
+
+/// Wait for a submitted sudo transaction and record its outcome. The events
+/// are returned when the wrapped call succeeded, otherwise the response to
+/// answer with: the job of a `wait=none` transaction or the dispatch error of
+/// the wrapped call.
+pub async fn sudo_outcome(
+    data: &AppState,
+    audit: &SudoAudit,
+    signer: &TxSigner,
+    result: Result<TxProgress<'static>, subxt::Error>,
+    wait: tx::Wait,
+) -> error::Result<Result<TxEvents, HttpResponse>> {
+    let progress = match result {
+        Ok(progress) => progress,
+        Err(e) => {
+            audit.finish("rejected", None, Some(&e.to_string())).await;
+            return Err(data.nonces.map_err(signer)(e));
+        }
+    };
+    let tx_hash = format!("{:?}", progress.extrinsic_hash());
+    // A `wait=none` call is recorded as pending before it is watched, the
+    // watcher records its final state
+    let on_done = match wait {
+        tx::Wait::None => {
+            audit.finish("pending", Some(&tx_hash), None).await;
+            let audit = audit.clone();
+            let tx_hash = tx_hash.clone();
+            let on_done: tx::OnDone =
+                Box::new(move |outcome| Box::pin(async move { audit.finish_job(outcome, &tx_hash).await }));
+            Some(on_done)
+        }
+        _ => None,
+    };
+    let events = match tx::try_wait_then(data, &audit.sub, signer, progress, wait, on_done).await {
+        Ok(tx::Submitted::Done(events)) => events,
+        Ok(tx::Submitted::Pending(job)) => return Ok(Err(HttpResponse::Accepted().json(job))),
+        Err(e) => {
+            audit.finish("rejected", Some(&tx_hash), Some(&e.to_string())).await;
+            return Err(map_subxt_err(e));
+        }
+    };
+    match dispatch_result(&events) {
+        Ok(Ok(())) => {
+            audit.finish("succeeded", Some(&tx_hash), None).await;
+            Ok(Ok(events))
+        }
+        Ok(Err(message)) => {
+            audit.finish("dispatch_failed", Some(&tx_hash), Some(&message)).await;
+            Ok(Err(HttpResponse::BadRequest().json(RequestError {
+                message: json!(format!("Sudo call failed: {}", message)),
+            })))
+        }
+        Err(e) => {
+            audit.finish("failed", Some(&tx_hash), Some(&e.to_string())).await;
+            Err(map_subxt_err(e))
+        }
+    }
+}
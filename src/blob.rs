@@ -0,0 +1,186 @@
+use crate::state::*;
+use crate::util::*;
+use actix_web::{error, http::header, web, HttpResponse};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Largest metadata document fetched from a remote blob uri
+const MAX_REMOTE_BLOB: usize = 1024 * 1024;
+
+/// Time allowed to fetch a document from a remote blob uri
+const REMOTE_BLOB_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Reference to an off-chain metadata document, written on chain instead of the document
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BlobPointer {
+    pub uri: String,
+    pub sha256: String,
+}
+
+/// Metadata documents stored in `--blob-dir`, named by the sha256 of their content
+pub struct BlobStore {
+    dir: PathBuf,
+    base_url: String,
+}
+
+fn is_sha256(value: &str) -> bool {
+    value.len() == 64 && value.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn sha256_hex(content: &[u8]) -> String {
+    hex::encode(sp_core::hashing::sha2_256(content))
+}
+
+impl BlobStore {
+    pub fn open(dir: PathBuf, base_url: String) -> std::io::Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        Ok(BlobStore { dir, base_url })
+    }
+
+    fn path(&self, sha256: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", sha256))
+    }
+
+    /// Whether `uri` points to this store, served by this or another instance of the API
+    fn serves(&self, uri: &str) -> bool {
+        uri.starts_with(&self.base_url)
+    }
+
+    /// Store a document, returns the pointer to write on chain
+    pub async fn put(&self, content: Vec<u8>) -> std::io::Result<BlobPointer> {
+        let sha256 = sha256_hex(&content);
+        let path = self.path(&sha256);
+        // Concurrent uploads of the same document each write their own file
+        let suffix = hex::encode(rand::thread_rng().gen::<[u8; 8]>());
+        let tmp = self.dir.join(format!("{}.{}.tmp", sha256, suffix));
+        web::block(move || write_blob(&path, &tmp, &content))
+            .await
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))??;
+        Ok(BlobPointer {
+            uri: format!("{}{}", self.base_url, sha256),
+            sha256,
+        })
+    }
+
+    /// Read a stored document, `None` when missing or when its content does not match the hash
+    pub async fn get(&self, sha256: &str) -> Option<Vec<u8>> {
+        if !is_sha256(sha256) {
+            return None;
+        }
+        let sha256 = sha256.to_lowercase();
+        let path = self.path(&sha256);
+        web::block(move || read_blob(&path, &sha256)).await.ok()?
+    }
+}
+
+/// Write `content` to `tmp` and move it to `path`. Documents are named by their
+/// hash, an existing `path` already holds the same content.
+fn write_blob(path: &Path, tmp: &Path, content: &[u8]) -> std::io::Result<()> {
+    if path.exists() {
+        return Ok(());
+    }
+    let written = std::fs::write(tmp, content).and_then(|_| std::fs::rename(tmp, path));
+    if written.is_err() {
+        let _ = std::fs::remove_file(tmp);
+        if path.exists() {
+            return Ok(());
+        }
+    }
+    written
+}
+
+fn read_blob(path: &Path, sha256: &str) -> Option<Vec<u8>> {
+    let content = std::fs::read(path).ok()?;
+    if sha256_hex(&content) != sha256 {
+        log::warn!("Blob {} does not match its hash", sha256);
+        return None;
+    }
+    Some(content)
+}
+
+/// Pointer written by `asset/create` for off-chain metadata
+pub fn parse_pointer(metadata: &serde_json::Value) -> Option<BlobPointer> {
+    let fields = metadata.as_object()?;
+    if fields.len() != 2 || !is_sha256(fields.get("sha256")?.as_str()?) {
+        return None;
+    }
+    serde_json::from_value(metadata.clone()).ok()
+}
+
+async fn fetch(pointer: &BlobPointer) -> Option<Vec<u8>> {
+    let mut response = awc::Client::new()
+        .get(&pointer.uri)
+        .timeout(REMOTE_BLOB_TIMEOUT)
+        .send()
+        .await
+        .ok()?;
+    if !response.status().is_success() {
+        return None;
+    }
+    let content = response.body().limit(MAX_REMOTE_BLOB).await.ok()?;
+    Some(content.to_vec())
+}
+
+/// Replace a blob pointer by the document it points to, from the local store or
+/// from its uri when the uri is under `--blob-url`. Other pointers, and documents
+/// that do not match their hash, are not resolved.
+pub async fn resolve(store: Option<&BlobStore>, metadata: serde_json::Value) -> serde_json::Value {
+    let pointer = match parse_pointer(&metadata) {
+        Some(pointer) => pointer,
+        None => return metadata,
+    };
+    let store = match store {
+        Some(store) => store,
+        None => return metadata,
+    };
+    let content = match store.get(&pointer.sha256).await {
+        Some(content) => Some(content),
+        None if store.serves(&pointer.uri) => fetch(&pointer)
+            .await
+            .filter(|content| sha256_hex(content) == pointer.sha256.to_lowercase()),
+        None => None,
+    };
+    content
+        .and_then(|content| serde_json::from_slice(&content).ok())
+        .unwrap_or(metadata)
+}
+
+/// Serve a stored metadata document
+pub async fn get(data: web::Data<AppState>, sha256: web::Path<String>) -> error::Result<HttpResponse> {
+    let content = match &data.blobs {
+        Some(store) => store.get(&sha256).await,
+        None => None,
+    };
+    match content {
+        Some(content) => Ok(HttpResponse::Ok()
+            .content_type("application/json")
+            .insert_header((header::ETAG, format!("\"{}\"", sha256.to_lowercase())))
+            .insert_header((header::CACHE_CONTROL, "public, max-age=31536000, immutable"))
+            .body(content)),
+        None => Ok(HttpResponse::NotFound().json(RequestError {
+            message: json!("Blob not found"),
+        })),
+    }
+}
+
+pub fn map_blob_err(e: std::io::Error) -> actix_web::Error {
+    let req_error = RequestError {
+        message: json!(format!("Blob store error: {}", e)),
+    };
+    let req_error = serde_json::to_string_pretty(&req_error).unwrap();
+    error::ErrorInternalServerError(req_error)
+}
+
+/// Get the blob store or fail the request if `--blob-dir` is not set
+pub fn require_blobs(blobs: &Option<std::sync::Arc<BlobStore>>) -> error::Result<&BlobStore> {
+    blobs.as_deref().ok_or_else(|| {
+        let req_error = RequestError {
+            message: json!("Blob store not configured, start the API with --blob-dir"),
+        };
+        let req_error = serde_json::to_string_pretty(&req_error).unwrap();
+        error::ErrorNotImplemented(req_error)
+    })
+}
@@ -5,11 +5,13 @@ use crate::sugarfunge;
 use crate::sugarfunge::runtime_types::frame_support::storage::bounded_vec::BoundedVec;
 use crate::util::*;
 use crate::user;
-use crate::config::Config;
+use crate::policy;
+use crate::signer;
+use crate::tx;
 use actix_web::{error, web, HttpResponse};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use subxt::PairSigner;
+use sp_core::Pair;
 use actix_web_middleware_keycloak_auth::KeycloakClaims;
 
 #[derive(Serialize, Deserialize)]
@@ -39,64 +41,48 @@ pub struct RegisterBundleOutput {
 pub async fn register_bundle(
     data: web::Data<AppState>,
     req: web::Json<RegisterBundleInput>,
+    query: web::Query<tx::WaitQuery>,
     claims: KeycloakClaims<user::ClaimsWithEmail>,
-    env: web::Data<Config>
 ) -> error::Result<HttpResponse> {
-    match user::get_seed(&claims.sub, env).await {
-        Ok(response) => {
-            if !response.seed.clone().unwrap_or_default().is_empty() {
-                let user_seed = response.seed.clone().unwrap();
-
-                let pair = get_pair_from_seed(&user_seed)?;
-                let signer = PairSigner::new(pair);
-                let bundle_id = sp_core::H256::from_str(&req.bundle_id).unwrap_or_default();
-                let schema = (
-                    BoundedVec(req.schema.class_ids.to_vec()),
-                    BoundedVec(req.schema.asset_ids.iter().map(|x| BoundedVec(x.to_vec())).collect()),
-                    BoundedVec(req.schema.amounts.iter().map(|x| BoundedVec(x.to_vec())).collect())
-                );
-                let metadata: Vec<u8> = serde_json::to_vec(&req.metadata).unwrap_or_default();
-                let api = data.api.lock().unwrap();
-                let result = api
-                .tx()
-                .bundle()
-                .register_bundle(
-                    req.class_id,
-                    req.asset_id,
-                    bundle_id,
-                    schema,
-                    metadata,
-                )
-                .sign_and_submit_then_watch(&signer)
-                    .await
-                    .map_err(map_subxt_err)?
-                    .wait_for_finalized_success()
-                    .await
-                    .map_err(map_subxt_err)?;
-                let result = result
-                    .find_first_event::<sugarfunge::bundle::events::Register>()
-                    .map_err(map_subxt_err)?;
-                match result {
-                    Some(event) => Ok(HttpResponse::Ok().json(RegisterBundleOutput {            
-                        who: event.who.to_string(),
-                        bundle_id: event.bundle_id.to_string(),
-                        class_id: event.class_id,
-                        asset_id: event.asset_id,
-                    })),
-                    None => Ok(HttpResponse::BadRequest().json(RequestError {
-                        message: json!("Failed to find sugarfunge::bundle::events::Register"),
-                    })),
-                }
-
-            } else {
-                Ok(HttpResponse::BadRequest().json(RequestError {
-                    message: json!("Not found user Attributes"),
-                }))
-            }
-        },
-        Err(_) => Ok(HttpResponse::BadRequest().json(RequestError {
-            message: json!("Failed to find user::getAttributes"),
-        }))
+    let bundle_id = sp_core::H256::from_str(&req.bundle_id).unwrap_or_default();
+    let signer = signer::pair_signer(&data, &claims.sub).await?;
+    let schema = (
+        BoundedVec(req.schema.class_ids.to_vec()),
+        BoundedVec(req.schema.asset_ids.iter().map(|x| BoundedVec(x.to_vec())).collect()),
+        BoundedVec(req.schema.amounts.iter().map(|x| BoundedVec(x.to_vec())).collect())
+    );
+    let metadata: Vec<u8> = serde_json::to_vec(&req.metadata).unwrap_or_default();
+    let api = data.api.get();
+    let result = api
+    .tx()
+    .bundle()
+    .register_bundle(
+        req.class_id,
+        req.asset_id,
+        bundle_id,
+        schema,
+        metadata,
+    )
+    .sign_and_submit_then_watch(&signer)
+        .await
+        .map_err(data.nonces.map_err(&signer))?;
+    let result = match tx::wait(&data, &claims.sub, &signer, result, query.wait).await? {
+        tx::Submitted::Done(events) => events,
+        tx::Submitted::Pending(job) => return Ok(HttpResponse::Accepted().json(job)),
+    };
+    let result = result
+        .find_first_event::<sugarfunge::bundle::events::Register>()
+        .map_err(map_subxt_err)?;
+    match result {
+        Some(event) => Ok(HttpResponse::Ok().json(RegisterBundleOutput {            
+            who: event.who.to_string(),
+            bundle_id: event.bundle_id.to_string(),
+            class_id: event.class_id,
+            asset_id: event.asset_id,
+        })),
+        None => Ok(HttpResponse::BadRequest().json(RequestError {
+            message: json!("Failed to find sugarfunge::bundle::events::Register"),
+        })),
     }
 }
 
@@ -120,62 +106,60 @@ pub struct MintBundleOutput {
 pub async fn mint_bundle(
     data: web::Data<AppState>,
     req: web::Json<MintBundleInput>,
+    query: web::Query<tx::WaitQuery>,
     claims: KeycloakClaims<user::ClaimsWithEmail>,
-    env: web::Data<Config>
 ) -> error::Result<HttpResponse> {
-    match user::get_seed(&claims.sub, env).await {
-        Ok(response) => {
-            if !response.seed.clone().unwrap_or_default().is_empty() {
-                let user_seed = response.seed.clone().unwrap();
-
-                let pair = get_pair_from_seed(&user_seed)?;
-                let signer = PairSigner::new(pair);
-                let account_from = sp_core::sr25519::Public::from_str(&req.from).map_err(map_account_err)?;
-                let account_to = sp_core::sr25519::Public::from_str(&req.to).map_err(map_account_err)?;
-                let account_from = sp_core::crypto::AccountId32::from(account_from);
-                let account_to = sp_core::crypto::AccountId32::from(account_to);
-                let bundle_id = sp_core::H256::from_str(&req.bundle_id).unwrap_or_default();
-                let api = data.api.lock().unwrap();
-                let result = api
-                .tx()
-                .bundle()
-                .mint_bundle(
-                    account_from,
-                    account_to,
-                    bundle_id,
-                    req.amount,
-                )
-                .sign_and_submit_then_watch(&signer)
-                    .await
-                    .map_err(map_subxt_err)?
-                    .wait_for_finalized_success()
-                    .await
-                    .map_err(map_subxt_err)?;
-                let result = result
-                    .find_first_event::<sugarfunge::bundle::events::Mint>()
-                    .map_err(map_subxt_err)?;
-                match result {
-                    Some(event) => Ok(HttpResponse::Ok().json(MintBundleOutput {            
-                        who: event.who.to_string(),
-                        from: event.from.to_string(),
-                        to: event.to.to_string(),
-                        bundle_id: event.bundle_id.to_string(),
-                        amount: event.amount,
-                    })),
-                    None => Ok(HttpResponse::BadRequest().json(RequestError {
-                        message: json!("Failed to find sugarfunge::bundle::events::Mint"),
-                    })),
-                }
-                
-            } else {
-                Ok(HttpResponse::BadRequest().json(RequestError {
-                    message: json!("Not found user Attributes"),
-                }))
-            }
-        },
-        Err(_) => Ok(HttpResponse::BadRequest().json(RequestError {
-            message: json!("Failed to find user::getAttributes"),
-        }))
+    let account_from = sp_core::sr25519::Public::from_str(&req.from).map_err(map_account_err)?;
+    let account_to = sp_core::sr25519::Public::from_str(&req.to).map_err(map_account_err)?;
+    let account_from = sp_core::crypto::AccountId32::from(account_from);
+    let account_to = sp_core::crypto::AccountId32::from(account_to);
+    let bundle_id = sp_core::H256::from_str(&req.bundle_id).unwrap_or_default();
+    let pair = signer::user_pair(&data, &claims.sub).await?;
+    let who = sp_core::crypto::AccountId32::from(pair.public());
+    let api = data.api.get();
+    let bundle = api
+        .storage()
+        .bundle()
+        .bundles(bundle_id, None)
+        .await
+        .map_err(map_subxt_err)?;
+    if let Some(bundle) = bundle {
+        let class_ids: std::collections::BTreeSet<u64> = bundle.schema.0 .0.into_iter().collect();
+        for class_id in class_ids {
+            policy::check_transfer(&api, class_id, &who).await?;
+        }
+    }
+    let signer = signer::tx_signer(&data, pair).await?;
+    let result = api
+    .tx()
+    .bundle()
+    .mint_bundle(
+        account_from,
+        account_to,
+        bundle_id,
+        req.amount,
+    )
+    .sign_and_submit_then_watch(&signer)
+        .await
+        .map_err(data.nonces.map_err(&signer))?;
+    let result = match tx::wait(&data, &claims.sub, &signer, result, query.wait).await? {
+        tx::Submitted::Done(events) => events,
+        tx::Submitted::Pending(job) => return Ok(HttpResponse::Accepted().json(job)),
+    };
+    let result = result
+        .find_first_event::<sugarfunge::bundle::events::Mint>()
+        .map_err(map_subxt_err)?;
+    match result {
+        Some(event) => Ok(HttpResponse::Ok().json(MintBundleOutput {            
+            who: event.who.to_string(),
+            from: event.from.to_string(),
+            to: event.to.to_string(),
+            bundle_id: event.bundle_id.to_string(),
+            amount: event.amount,
+        })),
+        None => Ok(HttpResponse::BadRequest().json(RequestError {
+            message: json!("Failed to find sugarfunge::bundle::events::Mint"),
+        })),
     }
 }
 
@@ -199,61 +183,45 @@ pub struct BurnBundleOutput {
 pub async fn burn_bundle(
     data: web::Data<AppState>,
     req: web::Json<BurnBundleInput>,
+    query: web::Query<tx::WaitQuery>,
     claims: KeycloakClaims<user::ClaimsWithEmail>,
-    env: web::Data<Config>
 ) -> error::Result<HttpResponse> {
-    match user::get_seed(&claims.sub, env).await {
-        Ok(response) => {
-            if !response.seed.clone().unwrap_or_default().is_empty() {
-                let user_seed = response.seed.clone().unwrap();
-
-                let pair = get_pair_from_seed(&user_seed)?;
-                let signer = PairSigner::new(pair);
-                let account_from = sp_core::sr25519::Public::from_str(&req.from).map_err(map_account_err)?;
-                let account_to = sp_core::sr25519::Public::from_str(&req.to).map_err(map_account_err)?;
-                let account_from = sp_core::crypto::AccountId32::from(account_from);
-                let account_to = sp_core::crypto::AccountId32::from(account_to);
-                let bundle_id = sp_core::H256::from_str(&req.bundle_id).unwrap_or_default();
-                let api = data.api.lock().unwrap();
-                let result = api
-                .tx()
-                .bundle()
-                .burn_bundle(
-                    account_from,
-                    account_to,
-                    bundle_id,
-                    req.amount,
-                )
-                .sign_and_submit_then_watch(&signer)
-                    .await
-                    .map_err(map_subxt_err)?
-                    .wait_for_finalized_success()
-                    .await
-                    .map_err(map_subxt_err)?;
-                let result = result
-                    .find_first_event::<sugarfunge::bundle::events::Burn>()
-                    .map_err(map_subxt_err)?;
-                match result {
-                    Some(event) => Ok(HttpResponse::Ok().json(BurnBundleOutput {            
-                        who: event.who.to_string(),
-                        from: event.from.to_string(),
-                        to: event.to.to_string(),
-                        bundle_id: event.bundle_id.to_string(),
-                        amount: event.amount,
-                    })),
-                    None => Ok(HttpResponse::BadRequest().json(RequestError {
-                        message: json!("Failed to find sugarfunge::bundle::events::Burn"),
-                    })),
-                }
-
-            } else {
-                Ok(HttpResponse::BadRequest().json(RequestError {
-                    message: json!("Not found user Attributes"),
-                }))
-            }
-        },
-        Err(_) => Ok(HttpResponse::BadRequest().json(RequestError {
-            message: json!("Failed to find user::getAttributes"),
-        }))
+    let account_from = sp_core::sr25519::Public::from_str(&req.from).map_err(map_account_err)?;
+    let account_to = sp_core::sr25519::Public::from_str(&req.to).map_err(map_account_err)?;
+    let account_from = sp_core::crypto::AccountId32::from(account_from);
+    let account_to = sp_core::crypto::AccountId32::from(account_to);
+    let bundle_id = sp_core::H256::from_str(&req.bundle_id).unwrap_or_default();
+    let signer = signer::pair_signer(&data, &claims.sub).await?;
+    let api = data.api.get();
+    let result = api
+    .tx()
+    .bundle()
+    .burn_bundle(
+        account_from,
+        account_to,
+        bundle_id,
+        req.amount,
+    )
+    .sign_and_submit_then_watch(&signer)
+        .await
+        .map_err(data.nonces.map_err(&signer))?;
+    let result = match tx::wait(&data, &claims.sub, &signer, result, query.wait).await? {
+        tx::Submitted::Done(events) => events,
+        tx::Submitted::Pending(job) => return Ok(HttpResponse::Accepted().json(job)),
+    };
+    let result = result
+        .find_first_event::<sugarfunge::bundle::events::Burn>()
+        .map_err(map_subxt_err)?;
+    match result {
+        Some(event) => Ok(HttpResponse::Ok().json(BurnBundleOutput {            
+            who: event.who.to_string(),
+            from: event.from.to_string(),
+            to: event.to.to_string(),
+            bundle_id: event.bundle_id.to_string(),
+            amount: event.amount,
+        })),
+        None => Ok(HttpResponse::BadRequest().json(RequestError {
+            message: json!("Failed to find sugarfunge::bundle::events::Burn"),
+        })),
     }
 }
\ No newline at end of file
@@ -0,0 +1,90 @@
+use crate::state::*;
+use crate::sugarfunge;
+use crate::util::*;
+use actix_web::error;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use subxt::{ClientBuilder, Signer};
+use url::Url;
+
+/// Connections to the node shared by every worker.
+///
+/// The runtime API is used through shared references, so reads and submissions
+/// run concurrently. Clients are created once at startup and live for the whole
+/// process, so a transaction submitted through one of them can still be watched
+/// after the request is answered.
+pub struct ClientPool {
+    clients: Vec<ClientAPI>,
+    next: AtomicUsize,
+}
+
+impl ClientPool {
+    pub async fn connect(url: &Url, size: usize) -> Result<Self, subxt::Error> {
+        let mut clients = Vec::with_capacity(size.max(1));
+        for _ in 0..size.max(1) {
+            let api = ClientBuilder::new()
+                .set_url(url.to_string())
+                .build()
+                .await?
+                .to_runtime_api::<sugarfunge::RuntimeApi<sugarfunge::DefaultConfig>>();
+            let api: ClientAPI = Box::leak(Box::new(api));
+            clients.push(api);
+        }
+        Ok(ClientPool {
+            clients,
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    /// Next client in round robin order
+    pub fn get(&self) -> ClientAPI {
+        let next = self.next.fetch_add(1, Ordering::Relaxed);
+        self.clients[next % self.clients.len()]
+    }
+}
+
+/// Hands out nonces per account so that extrinsics of the same signer can be
+/// submitted without waiting for the previous one to be included
+#[derive(Default)]
+pub struct NonceManager {
+    next: Mutex<HashMap<sp_core::crypto::AccountId32, u32>>,
+}
+
+impl NonceManager {
+    /// Set the nonce of `signer` to the next free nonce of its account
+    pub async fn assign(&self, api: &RuntimeApi, signer: &mut TxSigner) -> error::Result<()> {
+        let account = signer.account_id().clone();
+        let pending = api
+            .client
+            .rpc()
+            .system_account_next_index(&account)
+            .await
+            .map_err(map_subxt_err)?;
+        let nonce = {
+            let mut next = self.next.lock().unwrap();
+            let entry = next.entry(account).or_insert(pending);
+            if *entry < pending {
+                *entry = pending;
+            }
+            let nonce = *entry;
+            *entry += 1;
+            nonce
+        };
+        signer.set_nonce(nonce);
+        Ok(())
+    }
+
+    /// Forget the tracked nonce of an account, the next one is read from the node again
+    pub fn reset(&self, account: &sp_core::crypto::AccountId32) {
+        self.next.lock().unwrap().remove(account);
+    }
+
+    /// Map a submission error, resetting the nonce of the signer
+    pub fn map_err<'a>(&'a self, signer: &'a TxSigner) -> impl Fn(subxt::Error) -> actix_web::Error + 'a {
+        move |e| {
+            self.reset(signer.account_id());
+            map_subxt_err(e)
+        }
+    }
+}
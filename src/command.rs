@@ -1,3 +1,4 @@
+use std::path::PathBuf;
 use structopt::StructOpt;
 use url::Url;
 
@@ -12,6 +13,39 @@ pub struct Opt {
     pub node_server: Url,
     #[structopt(short = "l", long = "listen", default_value = "http://127.0.0.1:4000")]
     pub listen: Url,
+    /// Database URI, sqlite:// or postgres://
     #[structopt(short = "d", long = "db-uri")]
     pub db: Option<String>,
-}
\ No newline at end of file
+    #[structopt(
+        long = "signer",
+        default_value = "keycloak",
+        possible_values = &["keycloak", "keystore", "dev"]
+    )]
+    pub signer: String,
+    #[structopt(long = "keystore", default_value = "keystore.json", parse(from_os_str))]
+    pub keystore: PathBuf,
+    #[structopt(long = "pool-size", default_value = "4")]
+    pub pool_size: usize,
+    #[structopt(long = "workers", default_value = "4")]
+    pub workers: usize,
+    /// Seconds the responses of Idempotency-Key requests are kept
+    #[structopt(long = "idempotency-ttl", default_value = "86400")]
+    pub idempotency_ttl: u64,
+    /// First block indexed when the database has no indexed blocks yet
+    #[structopt(long = "index-from", default_value = "0")]
+    pub index_from: u32,
+    /// Directory of the blob store for off-chain asset metadata
+    #[structopt(long = "blob-dir", parse(from_os_str))]
+    pub blob_dir: Option<PathBuf>,
+    /// Public URL the blob store is served at, defaults to `<listen>blob/`
+    #[structopt(long = "blob-url")]
+    pub blob_url: Option<String>,
+    #[structopt(subcommand)]
+    pub cmd: Option<Command>,
+}
+
+#[derive(StructOpt, Debug)]
+pub enum Command {
+    /// Re-encrypt the user seeds stored in Keycloak with the current master key
+    RotateSeeds,
+}
@@ -1,3 +1,4 @@
+use crate::vault::MasterKeys;
 use std::env;
 
 
@@ -9,7 +10,11 @@ pub struct Config {
     pub keycloak_client_secret: String,
 
     pub keycloak_host: String,
-    pub keycloak_realm: String
+    pub keycloak_realm: String,
+    pub keycloak_admin_role: String,
+
+    pub keystore_password: Option<String>,
+    pub seed_master_keys: Option<MasterKeys>
 }
 
 pub fn init() -> Config {
@@ -41,5 +46,19 @@ pub fn init() -> Config {
             Ok(var) => var,
             Err(_) => panic!("KEYCLOAK_REALM {}", panic_message)
         },
+        keycloak_admin_role: env::var("KEYCLOAK_ADMIN_ROLE").unwrap_or_else(|_| "sugarfunge-admin".to_string()),
+        keystore_password: env::var("KEYSTORE_PASSWORD").ok(),
+        seed_master_keys: match env::var("SEED_MASTER_KEYS") {
+            Ok(keys) => {
+                let current = env::var("SEED_MASTER_KEY_VERSION").ok().map(|version| {
+                    version.parse::<u32>().expect("SEED_MASTER_KEY_VERSION is not a number")
+                });
+                match MasterKeys::parse(&keys, current) {
+                    Ok(keys) => Some(keys),
+                    Err(e) => panic!("SEED_MASTER_KEYS {}", e)
+                }
+            },
+            Err(_) => None
+        },
     }
 }
\ No newline at end of file
@@ -1,14 +1,15 @@
+use crate::audit;
 use crate::state::*;
 use crate::sugarfunge;
 use crate::util::*;
 use crate::user;
-use crate::config::Config;
+use crate::signer;
+use crate::tx;
 use actix_web::{error, web, HttpResponse};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use sp_core::Pair;
-use subxt::sp_runtime::traits::IdentifyAccount;
-use subxt::PairSigner;
+use std::str::FromStr;
 use sugarfunge::runtime_types::sugarfunge_primitives::CurrencyId;
 use actix_web_middleware_keycloak_auth::KeycloakClaims;
 
@@ -18,10 +19,30 @@ pub struct Currency {
     asset_id: u64,
 }
 
+/// How `currency/issue` limits the issued amount
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueMode {
+    /// The total issuance must stay within the supply registered in `Currency::CurrencyAssets`
+    Capped,
+    /// No limit, for currencies that are not registered in the currency pallet
+    Uncapped,
+}
+
+impl Default for IssueMode {
+    fn default() -> Self {
+        IssueMode::Capped
+    }
+}
+
 #[derive(Deserialize)]
 pub struct IssueCurrencyInput {
     currency: Currency,
-    amount: i128,
+    amount: u128,
+    /// Account receiving the issued amount, defaults to the signer
+    to: Option<String>,
+    #[serde(default)]
+    mode: IssueMode,
 }
 
 #[derive(Serialize)]
@@ -31,75 +52,139 @@ pub struct IssueCurrencyOutput {
     amount: i128,
 }
 
-/// Issue amount of currency
+#[derive(Serialize)]
+struct UpdateBalanceArgs {
+    who: String,
+    currency: Currency,
+    amount: i128,
+}
+
+fn issue_err(message: String) -> HttpResponse {
+    HttpResponse::BadRequest().json(RequestError {
+        message: json!(message),
+    })
+}
+
+/// Issue amount of currency to an account with a sudo `update_balance`
 pub async fn issue(
     data: web::Data<AppState>,
     req: web::Json<IssueCurrencyInput>,
+    query: web::Query<tx::WaitQuery>,
     claims: KeycloakClaims<user::ClaimsWithEmail>,
-    env: web::Data<Config>
 ) -> error::Result<HttpResponse> {
-    match user::get_seed(&claims.sub, env).await {
-        Ok(response) => {
-            if !response.seed.clone().unwrap_or_default().is_empty() {
-                let user_seed = response.seed.clone().unwrap();
-
-                let pair = get_pair_from_seed(&user_seed)?;
-                let who = pair.public().into_account();
-                let who = sp_core::crypto::AccountId32::from(who);
-                let who = subxt::sp_runtime::MultiAddress::Id(who);
-                let currency_id = CurrencyId(req.currency.class_id, req.currency.asset_id);
-                let signer = PairSigner::new(pair);
-                let api = data.api.lock().unwrap();
-                let result = api
-                    .storage()
-                    .orml_tokens()
-                    .total_issuance(currency_id, None)
-                    .await;
-                let total_issuance = result.map_err(map_subxt_err)?;
-                let currency_id = CurrencyId(req.currency.class_id, req.currency.asset_id);
-                let call = sugarfunge::runtime_types::sugarfunge_runtime::Call::OrmlCurrencies(
-                    sugarfunge::runtime_types::orml_currencies::module::Call::update_balance {
-                        who,
-                        currency_id,
-                        amount: req.amount.saturating_add(total_issuance as i128),
-                    },
-                );
-                let result = api
-                    .tx()
-                    .sudo()
-                    .sudo(call)
-                    .sign_and_submit_then_watch(&signer)
-                    .await
-                    .map_err(map_subxt_err)?
-                    .wait_for_finalized_success()
-                    .await
-                    .map_err(map_subxt_err)?;
-                let result = result
-                    .find_first_event::<sugarfunge::orml_currencies::events::BalanceUpdated>()
-                    .map_err(map_subxt_err)?;
-                match result {
-                    Some(event) => Ok(HttpResponse::Ok().json(IssueCurrencyOutput {
-                        currency: Currency {
-                            class_id: event.currency_id.0,
-                            asset_id: event.currency_id.1,
-                        },
-                        who: event.who.to_string(),
-                        amount: event.amount,
-                    })),
-                    None => Ok(HttpResponse::BadRequest().json(RequestError {
-                        message: json!("Failed to find sugarfunge::orml_currencies::events::BalanceUpdated"),
-                    })),
-                }
+    let to = match &req.to {
+        Some(to) => Some(sp_core::sr25519::Public::from_str(to).map_err(map_account_err)?),
+        None => None,
+    };
+    let amount = match i128::try_from(req.amount) {
+        Ok(amount) if amount > 0 => amount,
+        _ => return Ok(issue_err(format!("Amount must be between 1 and {}", i128::MAX))),
+    };
+    // The cap is checked against the total issuance, a capped issue has to be
+    // in a block before the next one is checked
+    if req.mode == IssueMode::Capped && query.wait == tx::Wait::None {
+        return Ok(issue_err(
+            "Capped issues cannot use wait=none, use wait=in_block or wait=finalized".to_string(),
+        ));
+    }
+    let pair = signer::user_pair(&data, &claims.sub).await?;
+    let account = sp_core::crypto::AccountId32::from(pair.public());
+    let who = match to {
+        Some(to) => sp_core::crypto::AccountId32::from(to),
+        None => account.clone(),
+    };
+    let api = data.api.get();
 
-            } else {
-                Ok(HttpResponse::BadRequest().json(RequestError {
-                    message: json!("Not found user Attributes"),
+    // Capped issues are serialized so that two requests cannot both pass the cap
+    let _capped = match req.mode {
+        IssueMode::Capped => Some(data.issuance.lock().await),
+        IssueMode::Uncapped => None,
+    };
+    if req.mode == IssueMode::Capped {
+        let currency_id = CurrencyId(req.currency.class_id, req.currency.asset_id);
+        let result = api
+            .storage()
+            .currency()
+            .currency_assets(currency_id, None)
+            .await;
+        let cap = match result.map_err(map_subxt_err)? {
+            Some(asset_info) => asset_info.total_supply,
+            None => {
+                return Ok(HttpResponse::NotFound().json(RequestError {
+                    message: json!("Currency is not registered in Currency::CurrencyAssets"),
                 }))
             }
+        };
+        let currency_id = CurrencyId(req.currency.class_id, req.currency.asset_id);
+        let result = api
+            .storage()
+            .orml_tokens()
+            .total_issuance(currency_id, None)
+            .await;
+        let total_issuance = result.map_err(map_subxt_err)?;
+        match total_issuance.checked_add(req.amount) {
+            Some(issued) if issued <= cap => {}
+            _ => {
+                return Ok(HttpResponse::Conflict().json(RequestError {
+                    message: json!(format!(
+                        "Issuing {} over the total issuance of {} exceeds the supply cap of {}",
+                        req.amount, total_issuance, cap
+                    )),
+                }))
+            }
+        }
+    }
+
+    let args = UpdateBalanceArgs {
+        who: who.to_string(),
+        currency: Currency {
+            class_id: req.currency.class_id,
+            asset_id: req.currency.asset_id,
+        },
+        amount,
+    };
+    let audit = audit::SudoAudit::start(
+        &data,
+        &claims.sub,
+        &account.to_string(),
+        "OrmlCurrencies.update_balance",
+        &args,
+    )
+    .await?;
+    let signer = signer::tx_signer(&data, pair).await?;
+    let currency_id = CurrencyId(req.currency.class_id, req.currency.asset_id);
+    let call = sugarfunge::runtime_types::sugarfunge_runtime::Call::OrmlCurrencies(
+        sugarfunge::runtime_types::orml_currencies::module::Call::update_balance {
+            who: subxt::sp_runtime::MultiAddress::Id(who),
+            currency_id,
+            amount,
         },
-        Err(_) => Ok(HttpResponse::BadRequest().json(RequestError {
-            message: json!("Failed to find user::getAttributes"),
-        }))
+    );
+    let result = api
+        .tx()
+        .sudo()
+        .sudo(call)
+        .sign_and_submit_then_watch(&signer)
+        .await;
+    let result = match audit::sudo_outcome(&data, &audit, &signer, result, query.wait).await? {
+        Ok(events) => events,
+        Err(response) => return Ok(response),
+    };
+    let result = result
+        .find_first_event::<sugarfunge::orml_currencies::events::BalanceUpdated>()
+        .map_err(map_subxt_err)?;
+    match result {
+        Some(event) => Ok(HttpResponse::Ok().json(IssueCurrencyOutput {
+            currency: Currency {
+                class_id: event.currency_id.0,
+                asset_id: event.currency_id.1,
+            },
+            who: event.who.to_string(),
+            amount: event.amount,
+        })),
+        None => Ok(HttpResponse::BadRequest().json(RequestError {
+            message: json!("Failed to find sugarfunge::orml_currencies::events::BalanceUpdated"),
+        })),
     }
 }
 
@@ -118,7 +203,7 @@ pub async fn issuance(
     data: web::Data<AppState>,
     req: web::Json<CurrencyIssuanceInput>,
 ) -> error::Result<HttpResponse> {
-    let api = data.api.lock().unwrap();
+    let api = data.api.get();
     let currency_id = CurrencyId(req.currency.class_id, req.currency.asset_id);
     let result = api
         .storage()
@@ -144,7 +229,7 @@ pub async fn supply(
     data: web::Data<AppState>,
     req: web::Json<CurrencySupplyInput>,
 ) -> error::Result<HttpResponse> {
-    let api = data.api.lock().unwrap();
+    let api = data.api.get();
     let currency_id = CurrencyId(req.currency.class_id, req.currency.asset_id);
     let result = api
         .storage()
@@ -160,6 +245,74 @@ pub async fn supply(
     Ok(HttpResponse::Ok().json(CurrencySupplyOutput { total_supply }))
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct CurrencyBalanceInput {
+    account: String,
+    currency: Currency,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CurrencyBalanceOutput {
+    free: u128,
+    reserved: u128,
+    frozen: u128,
+    /// Free balance not held by a lock
+    spendable: u128,
+    locks: Vec<CurrencyLockOutput>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CurrencyLockOutput {
+    /// Lock identifier, as text when it is printable or hex otherwise
+    id: String,
+    amount: u128,
+}
+
+fn lock_id(id: &[u8]) -> String {
+    match std::str::from_utf8(id) {
+        Ok(id) if id.chars().all(|c| c.is_ascii_graphic() || c == ' ') => id.trim_end().to_string(),
+        _ => format!("0x{}", hex::encode(id)),
+    }
+}
+
+/// Get free, reserved and frozen balance and the locks of an account for given currency
+pub async fn balance(
+    data: web::Data<AppState>,
+    req: web::Json<CurrencyBalanceInput>,
+) -> error::Result<HttpResponse> {
+    let account = sp_core::sr25519::Public::from_str(&req.account).map_err(map_account_err)?;
+    let account = sp_core::crypto::AccountId32::from(account);
+    let api = data.api.get();
+    let currency_id = CurrencyId(req.currency.class_id, req.currency.asset_id);
+    let result = api
+        .storage()
+        .orml_tokens()
+        .accounts(account.clone(), currency_id, None)
+        .await;
+    let account_data = result.map_err(map_subxt_err)?;
+    let currency_id = CurrencyId(req.currency.class_id, req.currency.asset_id);
+    let result = api
+        .storage()
+        .orml_tokens()
+        .locks(account, currency_id, None)
+        .await;
+    let locks = result.map_err(map_subxt_err)?;
+    Ok(HttpResponse::Ok().json(CurrencyBalanceOutput {
+        free: account_data.free,
+        reserved: account_data.reserved,
+        frozen: account_data.frozen,
+        spendable: account_data.free.saturating_sub(account_data.frozen),
+        locks: locks
+            .0
+            .into_iter()
+            .map(|lock| CurrencyLockOutput {
+                id: lock_id(&lock.id),
+                amount: lock.amount,
+            })
+            .collect(),
+    }))
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct MintCurrencyInput {
     currency: Currency,
@@ -177,54 +330,38 @@ pub struct MintCurrencyOutput {
 pub async fn mint(
     data: web::Data<AppState>,
     req: web::Json<MintCurrencyInput>,
+    query: web::Query<tx::WaitQuery>,
     claims: KeycloakClaims<user::ClaimsWithEmail>,
-    env: web::Data<Config>
 ) -> error::Result<HttpResponse> {
-    match user::get_seed(&claims.sub, env).await {
-        Ok(response) => {
-            if !response.seed.clone().unwrap_or_default().is_empty() {
-                let user_seed = response.seed.clone().unwrap();
-
-                let pair = get_pair_from_seed(&user_seed)?;
-                let signer = PairSigner::new(pair);
-                let currency_id = CurrencyId(req.currency.class_id, req.currency.asset_id);
-                let api = data.api.lock().unwrap();
-                let result = api
-                    .tx()
-                    .currency()
-                    .mint(currency_id, req.amount)
-                    .sign_and_submit_then_watch(&signer)
-                    .await
-                    .map_err(map_subxt_err)?
-                    .wait_for_finalized_success()
-                    .await
-                    .map_err(map_subxt_err)?;
-                let result = result
-                    .find_first_event::<sugarfunge::currency::events::Mint>()
-                    .map_err(map_subxt_err)?;
-                match result {
-                    Some(event) => Ok(HttpResponse::Ok().json(MintCurrencyOutput {
-                        currency: Currency {
-                            class_id: event.currency_id.0,
-                            asset_id: event.currency_id.1,
-                        },
-                        amount: event.amount,
-                        who: event.who.to_string(),
-                    })),
-                    None => Ok(HttpResponse::BadRequest().json(RequestError {
-                        message: json!("Failed to find sugarfunge::currency::events::Mint"),
-                    })),
-                }
-
-            } else {
-                Ok(HttpResponse::BadRequest().json(RequestError {
-                    message: json!("Not found user Attributes"),
-                }))
-            }
-        },
-        Err(_) => Ok(HttpResponse::BadRequest().json(RequestError {
-            message: json!("Failed to find user::getAttributes"),
-        }))
+    let signer = signer::pair_signer(&data, &claims.sub).await?;
+    let currency_id = CurrencyId(req.currency.class_id, req.currency.asset_id);
+    let api = data.api.get();
+    let result = api
+        .tx()
+        .currency()
+        .mint(currency_id, req.amount)
+        .sign_and_submit_then_watch(&signer)
+        .await
+        .map_err(data.nonces.map_err(&signer))?;
+    let result = match tx::wait(&data, &claims.sub, &signer, result, query.wait).await? {
+        tx::Submitted::Done(events) => events,
+        tx::Submitted::Pending(job) => return Ok(HttpResponse::Accepted().json(job)),
+    };
+    let result = result
+        .find_first_event::<sugarfunge::currency::events::Mint>()
+        .map_err(map_subxt_err)?;
+    match result {
+        Some(event) => Ok(HttpResponse::Ok().json(MintCurrencyOutput {
+            currency: Currency {
+                class_id: event.currency_id.0,
+                asset_id: event.currency_id.1,
+            },
+            amount: event.amount,
+            who: event.who.to_string(),
+        })),
+        None => Ok(HttpResponse::BadRequest().json(RequestError {
+            message: json!("Failed to find sugarfunge::currency::events::Mint"),
+        })),
     }
 }
 
@@ -245,54 +382,110 @@ pub struct BurnCurrencyOutput {
 pub async fn burn(
     data: web::Data<AppState>,
     req: web::Json<BurnCurrencyInput>,
+    query: web::Query<tx::WaitQuery>,
     claims: KeycloakClaims<user::ClaimsWithEmail>,
-    env: web::Data<Config>
 ) -> error::Result<HttpResponse> {
-    match user::get_seed(&claims.sub, env).await {
-        Ok(response) => {
-            if !response.seed.clone().unwrap_or_default().is_empty() {
-                let user_seed = response.seed.clone().unwrap();
+    let signer = signer::pair_signer(&data, &claims.sub).await?;
+    let currency_id = CurrencyId(req.currency.class_id, req.currency.asset_id);
+    let api = data.api.get();
+    let result = api
+        .tx()
+        .currency()
+        .burn(currency_id, req.amount)
+        .sign_and_submit_then_watch(&signer)
+        .await
+        .map_err(data.nonces.map_err(&signer))?;
+    let result = match tx::wait(&data, &claims.sub, &signer, result, query.wait).await? {
+        tx::Submitted::Done(events) => events,
+        tx::Submitted::Pending(job) => return Ok(HttpResponse::Accepted().json(job)),
+    };
+    let result = result
+        .find_first_event::<sugarfunge::currency::events::Burn>()
+        .map_err(map_subxt_err)?;
+    match result {
+        Some(event) => Ok(HttpResponse::Ok().json(BurnCurrencyOutput {
+            currency: Currency {
+                class_id: event.currency_id.0,
+                asset_id: event.currency_id.1,
+            },
+            amount: event.amount,
+            who: event.who.to_string(),
+        })),
+        None => Ok(HttpResponse::BadRequest().json(RequestError {
+            message: json!("Failed to find sugarfunge::currency::events::Burn"),
+        })),
+    }
+}
 
-                let pair = get_pair_from_seed(&user_seed)?;
-                let signer = PairSigner::new(pair);
-                let currency_id = CurrencyId(req.currency.class_id, req.currency.asset_id);
-                let api = data.api.lock().unwrap();
-                let result = api
-                    .tx()
-                    .currency()
-                    .burn(currency_id, req.amount)
-                    .sign_and_submit_then_watch(&signer)
-                    .await
-                    .map_err(map_subxt_err)?
-                    .wait_for_finalized_success()
-                    .await
-                    .map_err(map_subxt_err)?;
-                let result = result
-                    .find_first_event::<sugarfunge::currency::events::Burn>()
-                    .map_err(map_subxt_err)?;
-                match result {
-                    Some(event) => Ok(HttpResponse::Ok().json(BurnCurrencyOutput {
-                        currency: Currency {
-                            class_id: event.currency_id.0,
-                            asset_id: event.currency_id.1,
-                        },
-                        amount: event.amount,
-                        who: event.who.to_string(),
-                    })),
-                    None => Ok(HttpResponse::BadRequest().json(RequestError {
-                        message: json!("Failed to find sugarfunge::currency::events::Burn"),
-                    })),
-                }
+#[derive(Serialize, Deserialize)]
+pub struct TransferCurrencyInput {
+    to: String,
+    currency: Currency,
+    amount: u128,
+}
 
-            } else {
-                Ok(HttpResponse::BadRequest().json(RequestError {
-                    message: json!("Not found user Attributes"),
-                }))
-            }
-        },
-        Err(_) => Ok(HttpResponse::BadRequest().json(RequestError {
-            message: json!("Failed to find user::getAttributes"),
-        }))
-    }
+#[derive(Serialize, Deserialize)]
+pub struct TransferCurrencyOutput {
+    currency: Currency,
+    from: String,
+    to: String,
+    amount: u128,
 }
 
+/// Transfer amount of currency from the signer to an account
+pub async fn transfer(
+    data: web::Data<AppState>,
+    req: web::Json<TransferCurrencyInput>,
+    query: web::Query<tx::WaitQuery>,
+    claims: KeycloakClaims<user::ClaimsWithEmail>,
+) -> error::Result<HttpResponse> {
+    let to = sp_core::sr25519::Public::from_str(&req.to).map_err(map_account_err)?;
+    let to = sp_core::crypto::AccountId32::from(to);
+    let to = subxt::sp_runtime::MultiAddress::Id(to);
+    let signer = signer::pair_signer(&data, &claims.sub).await?;
+    let currency_id = CurrencyId(req.currency.class_id, req.currency.asset_id);
+    let api = data.api.get();
+    let result = api
+        .tx()
+        .orml_currencies()
+        .transfer(to, currency_id, req.amount)
+        .sign_and_submit_then_watch(&signer)
+        .await
+        .map_err(data.nonces.map_err(&signer))?;
+    let result = match tx::wait(&data, &claims.sub, &signer, result, query.wait).await? {
+        tx::Submitted::Done(events) => events,
+        tx::Submitted::Pending(job) => return Ok(HttpResponse::Accepted().json(job)),
+    };
+    let tokens_event = result
+        .find_first_event::<sugarfunge::orml_tokens::events::Transfer>()
+        .map_err(map_subxt_err)?;
+    if let Some(event) = tokens_event {
+        return Ok(HttpResponse::Ok().json(TransferCurrencyOutput {
+            currency: Currency {
+                class_id: event.currency_id.0,
+                asset_id: event.currency_id.1,
+            },
+            from: event.from.to_string(),
+            to: event.to.to_string(),
+            amount: event.amount,
+        }));
+    }
+    // The native currency is moved by the Balances pallet
+    let balances_event = result
+        .find_first_event::<sugarfunge::balances::events::Transfer>()
+        .map_err(map_subxt_err)?;
+    match balances_event {
+        Some(event) => Ok(HttpResponse::Ok().json(TransferCurrencyOutput {
+            currency: Currency {
+                class_id: req.currency.class_id,
+                asset_id: req.currency.asset_id,
+            },
+            from: event.from.to_string(),
+            to: event.to.to_string(),
+            amount: event.amount,
+        })),
+        None => Ok(HttpResponse::BadRequest().json(RequestError {
+            message: json!("Failed to find sugarfunge::orml_tokens::events::Transfer"),
+        })),
+    }
+}
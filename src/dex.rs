@@ -2,12 +2,14 @@ use crate::state::*;
 use crate::sugarfunge;
 use crate::util::*;
 use crate::user;
-use crate::config::Config;
+use crate::policy;
+use crate::signer;
+use crate::tx;
 use actix_web::{error, web, HttpResponse};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use sp_core::Pair;
 use std::str::FromStr;
-use subxt::PairSigner;
 use sugarfunge::runtime_types::sugarfunge_primitives::CurrencyId;
 use actix_web_middleware_keycloak_auth::KeycloakClaims;
 
@@ -35,55 +37,39 @@ pub struct CreateDexOutput {
 pub async fn create(
     data: web::Data<AppState>,
     req: web::Json<CreateDexInput>,
+    query: web::Query<tx::WaitQuery>,
     claims: KeycloakClaims<user::ClaimsWithEmail>,
-    env: web::Data<Config>
 ) -> error::Result<HttpResponse> {
-    match user::get_seed(&claims.sub, env).await {
-        Ok(response) => {
-            if !response.seed.clone().unwrap_or_default().is_empty() {
-                let user_seed = response.seed.clone().unwrap();
-
-                let pair = get_pair_from_seed(&user_seed)?;
-                let signer = PairSigner::new(pair);
-                let currency_id = CurrencyId(req.currency.class_id, req.currency.asset_id);
-                let api = data.api.lock().unwrap();
-                let result = api
-                    .tx()
-                    .dex()
-                    .create_exchange(
-                        req.exchange_id,
-                        currency_id,
-                        req.asset_class_id,
-                        req.lp_class_id,
-                    )
-                    .sign_and_submit_then_watch(&signer)
-                    .await
-                    .map_err(map_subxt_err)?
-                    .wait_for_finalized_success()
-                    .await
-                    .map_err(map_subxt_err)?;
-                let result = result
-                    .find_first_event::<sugarfunge::dex::events::ExchangeCreated>()
-                    .map_err(map_subxt_err)?;
-                match result {
-                    Some(event) => Ok(HttpResponse::Ok().json(CreateDexOutput {
-                        exchange_id: event.exchange_id,
-                        who: event.who.to_string(),
-                    })),
-                    None => Ok(HttpResponse::BadRequest().json(RequestError {
-                        message: json!("Failed to find sugarfunge::balances::events::Transfer"),
-                    })),
-                }
-
-            } else {
-                Ok(HttpResponse::BadRequest().json(RequestError {
-                    message: json!("Not found user Attributes"),
-                }))
-            }
-        },
-        Err(_) => Ok(HttpResponse::BadRequest().json(RequestError {
-            message: json!("Failed to find user::getAttributes"),
-        }))
+    let signer = signer::pair_signer(&data, &claims.sub).await?;
+    let currency_id = CurrencyId(req.currency.class_id, req.currency.asset_id);
+    let api = data.api.get();
+    let result = api
+        .tx()
+        .dex()
+        .create_exchange(
+            req.exchange_id,
+            currency_id,
+            req.asset_class_id,
+            req.lp_class_id,
+        )
+        .sign_and_submit_then_watch(&signer)
+        .await
+        .map_err(data.nonces.map_err(&signer))?;
+    let result = match tx::wait(&data, &claims.sub, &signer, result, query.wait).await? {
+        tx::Submitted::Done(events) => events,
+        tx::Submitted::Pending(job) => return Ok(HttpResponse::Accepted().json(job)),
+    };
+    let result = result
+        .find_first_event::<sugarfunge::dex::events::ExchangeCreated>()
+        .map_err(map_subxt_err)?;
+    match result {
+        Some(event) => Ok(HttpResponse::Ok().json(CreateDexOutput {
+            exchange_id: event.exchange_id,
+            who: event.who.to_string(),
+        })),
+        None => Ok(HttpResponse::BadRequest().json(RequestError {
+            message: json!("Failed to find sugarfunge::balances::events::Transfer"),
+        })),
     }
 }
 
@@ -110,61 +96,45 @@ pub struct BuyAssetsOutput {
 pub async fn buy_assets(
     data: web::Data<AppState>,
     req: web::Json<BuyAssetsInput>,
+    query: web::Query<tx::WaitQuery>,
     claims: KeycloakClaims<user::ClaimsWithEmail>,
-    env: web::Data<Config>
 ) -> error::Result<HttpResponse> {
-    match user::get_seed(&claims.sub, env).await {
-        Ok(response) => {
-            if !response.seed.clone().unwrap_or_default().is_empty() {
-                let user_seed = response.seed.clone().unwrap();
-
-                let pair = get_pair_from_seed(&user_seed)?;
-                let signer = PairSigner::new(pair);
-                let to = sp_core::sr25519::Public::from_str(&req.to).map_err(map_account_err)?;
-                let to = sp_core::crypto::AccountId32::from(to);
-                let api = data.api.lock().unwrap();
-                let result = api
-                    .tx()
-                    .dex()
-                    .buy_assets(
-                        req.exchange_id,
-                        req.asset_ids.clone(),
-                        req.asset_amounts_out.clone(),
-                        req.max_currency,
-                        to,
-                    )
-                    .sign_and_submit_then_watch(&signer)
-                    .await
-                    .map_err(map_subxt_err)?
-                    .wait_for_finalized_success()
-                    .await
-                    .map_err(map_subxt_err)?;
-                let result = result
-                    .find_first_event::<sugarfunge::dex::events::CurrencyToAsset>()
-                    .map_err(map_subxt_err)?;
-                match result {
-                    Some(event) => Ok(HttpResponse::Ok().json(BuyAssetsOutput {
-                        exchange_id: event.exchange_id,
-                        who: event.who.to_string(),
-                        to: event.to.to_string(),
-                        asset_ids: event.asset_ids,
-                        asset_amounts_out: event.asset_amounts_out,
-                        currency_amounts_in: event.currency_amounts_in,
-                    })),
-                    None => Ok(HttpResponse::BadRequest().json(RequestError {
-                        message: json!("Failed to find sugarfunge::dex::events::CurrencyToAsset"),
-                    })),
-                }
-
-            } else {
-                Ok(HttpResponse::BadRequest().json(RequestError {
-                    message: json!("Not found user Attributes"),
-                }))
-            }
-        },
-        Err(_) => Ok(HttpResponse::BadRequest().json(RequestError {
-            message: json!("Failed to find user::getAttributes"),
-        }))
+    let to = sp_core::sr25519::Public::from_str(&req.to).map_err(map_account_err)?;
+    let to = sp_core::crypto::AccountId32::from(to);
+    let signer = signer::pair_signer(&data, &claims.sub).await?;
+    let api = data.api.get();
+    let result = api
+        .tx()
+        .dex()
+        .buy_assets(
+            req.exchange_id,
+            req.asset_ids.clone(),
+            req.asset_amounts_out.clone(),
+            req.max_currency,
+            to,
+        )
+        .sign_and_submit_then_watch(&signer)
+        .await
+        .map_err(data.nonces.map_err(&signer))?;
+    let result = match tx::wait(&data, &claims.sub, &signer, result, query.wait).await? {
+        tx::Submitted::Done(events) => events,
+        tx::Submitted::Pending(job) => return Ok(HttpResponse::Accepted().json(job)),
+    };
+    let result = result
+        .find_first_event::<sugarfunge::dex::events::CurrencyToAsset>()
+        .map_err(map_subxt_err)?;
+    match result {
+        Some(event) => Ok(HttpResponse::Ok().json(BuyAssetsOutput {
+            exchange_id: event.exchange_id,
+            who: event.who.to_string(),
+            to: event.to.to_string(),
+            asset_ids: event.asset_ids,
+            asset_amounts_out: event.asset_amounts_out,
+            currency_amounts_in: event.currency_amounts_in,
+        })),
+        None => Ok(HttpResponse::BadRequest().json(RequestError {
+            message: json!("Failed to find sugarfunge::dex::events::CurrencyToAsset"),
+        })),
     }
 }
 
@@ -191,61 +161,56 @@ pub struct SellAssetsOutput {
 pub async fn sell_assets(
     data: web::Data<AppState>,
     req: web::Json<SellAssetsInput>,
+    query: web::Query<tx::WaitQuery>,
     claims: KeycloakClaims<user::ClaimsWithEmail>,
-    env: web::Data<Config>
 ) -> error::Result<HttpResponse> {
-    match user::get_seed(&claims.sub, env).await {
-        Ok(response) => {
-            if !response.seed.clone().unwrap_or_default().is_empty() {
-                let user_seed = response.seed.clone().unwrap();
-
-                let pair = get_pair_from_seed(&user_seed)?;
-                let signer = PairSigner::new(pair);
-                let to = sp_core::sr25519::Public::from_str(&req.to).map_err(map_account_err)?;
-                let to = sp_core::crypto::AccountId32::from(to);
-                let api = data.api.lock().unwrap();
-                let result = api
-                    .tx()
-                    .dex()
-                    .sell_assets(
-                        req.exchange_id,
-                        req.asset_ids.clone(),
-                        req.asset_amounts_in.clone(),
-                        req.min_currency,
-                        to,
-                    )
-                    .sign_and_submit_then_watch(&signer)
-                    .await
-                    .map_err(map_subxt_err)?
-                    .wait_for_finalized_success()
-                    .await
-                    .map_err(map_subxt_err)?;
-                let result = result
-                    .find_first_event::<sugarfunge::dex::events::AssetToCurrency>()
-                    .map_err(map_subxt_err)?;
-                match result {
-                    Some(event) => Ok(HttpResponse::Ok().json(SellAssetsOutput {
-                        exchange_id: event.exchange_id,
-                        who: event.who.to_string(),
-                        to: event.to.to_string(),
-                        asset_ids: event.asset_ids,
-                        asset_amounts_in: event.asset_amounts_in,
-                        currency_amounts_out: event.currency_amounts_out,
-                    })),
-                    None => Ok(HttpResponse::BadRequest().json(RequestError {
-                        message: json!("Failed to find sugarfunge::dex::events::CurrencyToAsset"),
-                    })),
-                }
-
-            } else {
-                Ok(HttpResponse::BadRequest().json(RequestError {
-                    message: json!("Not found user Attributes"),
-                }))
-            }
-        },
-        Err(_) => Ok(HttpResponse::BadRequest().json(RequestError {
-            message: json!("Failed to find user::getAttributes"),
-        }))
+    let to = sp_core::sr25519::Public::from_str(&req.to).map_err(map_account_err)?;
+    let to = sp_core::crypto::AccountId32::from(to);
+    let pair = signer::user_pair(&data, &claims.sub).await?;
+    let who = sp_core::crypto::AccountId32::from(pair.public());
+    let api = data.api.get();
+    let exchange = api
+        .storage()
+        .dex()
+        .exchanges(req.exchange_id, None)
+        .await
+        .map_err(map_subxt_err)?;
+    if let Some(exchange) = exchange {
+        policy::check_transfer(&api, exchange.asset_class_id, &who).await?;
+    }
+    let signer = signer::tx_signer(&data, pair).await?;
+    let result = api
+        .tx()
+        .dex()
+        .sell_assets(
+            req.exchange_id,
+            req.asset_ids.clone(),
+            req.asset_amounts_in.clone(),
+            req.min_currency,
+            to,
+        )
+        .sign_and_submit_then_watch(&signer)
+        .await
+        .map_err(data.nonces.map_err(&signer))?;
+    let result = match tx::wait(&data, &claims.sub, &signer, result, query.wait).await? {
+        tx::Submitted::Done(events) => events,
+        tx::Submitted::Pending(job) => return Ok(HttpResponse::Accepted().json(job)),
+    };
+    let result = result
+        .find_first_event::<sugarfunge::dex::events::AssetToCurrency>()
+        .map_err(map_subxt_err)?;
+    match result {
+        Some(event) => Ok(HttpResponse::Ok().json(SellAssetsOutput {
+            exchange_id: event.exchange_id,
+            who: event.who.to_string(),
+            to: event.to.to_string(),
+            asset_ids: event.asset_ids,
+            asset_amounts_in: event.asset_amounts_in,
+            currency_amounts_out: event.currency_amounts_out,
+        })),
+        None => Ok(HttpResponse::BadRequest().json(RequestError {
+            message: json!("Failed to find sugarfunge::dex::events::CurrencyToAsset"),
+        })),
     }
 }
 
@@ -272,61 +237,56 @@ pub struct AddLiquidityOutput {
 pub async fn add_liquidity(
     data: web::Data<AppState>,
     req: web::Json<AddLiquidityInput>,
+    query: web::Query<tx::WaitQuery>,
     claims: KeycloakClaims<user::ClaimsWithEmail>,
-    env: web::Data<Config>
 ) -> error::Result<HttpResponse> {
-    match user::get_seed(&claims.sub, env).await {
-        Ok(response) => {
-            if !response.seed.clone().unwrap_or_default().is_empty() {
-                let user_seed = response.seed.clone().unwrap();
-
-                let pair = get_pair_from_seed(&user_seed)?;
-                let signer = PairSigner::new(pair);
-                let to = sp_core::sr25519::Public::from_str(&req.to).map_err(map_account_err)?;
-                let to = sp_core::crypto::AccountId32::from(to);
-                let api = data.api.lock().unwrap();
-                let result = api
-                    .tx()
-                    .dex()
-                    .add_liquidity(
-                        req.exchange_id,
-                        to,
-                        req.asset_ids.clone(),
-                        req.asset_amounts.clone(),
-                        req.max_currencies.clone(),
-                    )
-                    .sign_and_submit_then_watch(&signer)
-                    .await
-                    .map_err(map_subxt_err)?
-                    .wait_for_finalized_success()
-                    .await
-                    .map_err(map_subxt_err)?;
-                let result = result
-                    .find_first_event::<sugarfunge::dex::events::LiquidityAdded>()
-                    .map_err(map_subxt_err)?;
-                match result {
-                    Some(event) => Ok(HttpResponse::Ok().json(AddLiquidityOutput {
-                        exchange_id: event.exchange_id,
-                        who: event.who.to_string(),
-                        to: event.to.to_string(),
-                        asset_ids: event.asset_ids,
-                        asset_amounts: event.asset_amounts,
-                        currency_amounts: event.currency_amounts,
-                    })),
-                    None => Ok(HttpResponse::BadRequest().json(RequestError {
-                        message: json!("Failed to find sugarfunge::dex::events::CurrencyToAsset"),
-                    })),
-                }
-
-            } else {
-                Ok(HttpResponse::BadRequest().json(RequestError {
-                    message: json!("Not found user Attributes"),
-                }))
-            }
-        },
-        Err(_) => Ok(HttpResponse::BadRequest().json(RequestError {
-            message: json!("Failed to find user::getAttributes"),
-        }))
+    let to = sp_core::sr25519::Public::from_str(&req.to).map_err(map_account_err)?;
+    let to = sp_core::crypto::AccountId32::from(to);
+    let pair = signer::user_pair(&data, &claims.sub).await?;
+    let who = sp_core::crypto::AccountId32::from(pair.public());
+    let api = data.api.get();
+    let exchange = api
+        .storage()
+        .dex()
+        .exchanges(req.exchange_id, None)
+        .await
+        .map_err(map_subxt_err)?;
+    if let Some(exchange) = exchange {
+        policy::check_transfer(&api, exchange.asset_class_id, &who).await?;
+    }
+    let signer = signer::tx_signer(&data, pair).await?;
+    let result = api
+        .tx()
+        .dex()
+        .add_liquidity(
+            req.exchange_id,
+            to,
+            req.asset_ids.clone(),
+            req.asset_amounts.clone(),
+            req.max_currencies.clone(),
+        )
+        .sign_and_submit_then_watch(&signer)
+        .await
+        .map_err(data.nonces.map_err(&signer))?;
+    let result = match tx::wait(&data, &claims.sub, &signer, result, query.wait).await? {
+        tx::Submitted::Done(events) => events,
+        tx::Submitted::Pending(job) => return Ok(HttpResponse::Accepted().json(job)),
+    };
+    let result = result
+        .find_first_event::<sugarfunge::dex::events::LiquidityAdded>()
+        .map_err(map_subxt_err)?;
+    match result {
+        Some(event) => Ok(HttpResponse::Ok().json(AddLiquidityOutput {
+            exchange_id: event.exchange_id,
+            who: event.who.to_string(),
+            to: event.to.to_string(),
+            asset_ids: event.asset_ids,
+            asset_amounts: event.asset_amounts,
+            currency_amounts: event.currency_amounts,
+        })),
+        None => Ok(HttpResponse::BadRequest().json(RequestError {
+            message: json!("Failed to find sugarfunge::dex::events::CurrencyToAsset"),
+        })),
     }
 }
 
@@ -354,61 +314,45 @@ pub struct RemoveLiquidityOutput {
 pub async fn remove_liquidity(
     data: web::Data<AppState>,
     req: web::Json<RemoveLiquidityInput>,
+    query: web::Query<tx::WaitQuery>,
     claims: KeycloakClaims<user::ClaimsWithEmail>,
-    env: web::Data<Config>
 ) -> error::Result<HttpResponse> {
-    match user::get_seed(&claims.sub, env).await {
-        Ok(response) => {
-            if !response.seed.clone().unwrap_or_default().is_empty() {
-                let user_seed = response.seed.clone().unwrap();
-
-                let pair = get_pair_from_seed(&user_seed)?;
-                let signer = PairSigner::new(pair);
-                let to = sp_core::sr25519::Public::from_str(&req.to).map_err(map_account_err)?;
-                let to = sp_core::crypto::AccountId32::from(to);
-                let api = data.api.lock().unwrap();
-                let result = api
-                    .tx()
-                    .dex()
-                    .remove_liquidity(
-                        req.exchange_id,
-                        to,
-                        req.asset_ids.clone(),
-                        req.liquidities.clone(),
-                        req.min_currencies.clone(),
-                        req.min_assets.clone(),
-                    )
-                    .sign_and_submit_then_watch(&signer)
-                    .await
-                    .map_err(map_subxt_err)?
-                    .wait_for_finalized_success()
-                    .await
-                    .map_err(map_subxt_err)?;
-                let result = result
-                    .find_first_event::<sugarfunge::dex::events::LiquidityRemoved>()
-                    .map_err(map_subxt_err)?;
-                match result {
-                    Some(event) => Ok(HttpResponse::Ok().json(RemoveLiquidityOutput {
-                        exchange_id: event.exchange_id,
-                        who: event.who.to_string(),
-                        to: event.to.to_string(),
-                        asset_ids: event.asset_ids,
-                        asset_amounts: event.asset_amounts,
-                        currency_amounts: event.currency_amounts,
-                    })),
-                    None => Ok(HttpResponse::BadRequest().json(RequestError {
-                        message: json!("Failed to find sugarfunge::dex::events::CurrencyToAsset"),
-                    })),
-                }
-
-            } else {
-                Ok(HttpResponse::BadRequest().json(RequestError {
-                    message: json!("Not found user Attributes"),
-                }))
-            }
-        },
-        Err(_) => Ok(HttpResponse::BadRequest().json(RequestError {
-            message: json!("Failed to find user::getAttributes"),
-        }))
+    let to = sp_core::sr25519::Public::from_str(&req.to).map_err(map_account_err)?;
+    let to = sp_core::crypto::AccountId32::from(to);
+    let signer = signer::pair_signer(&data, &claims.sub).await?;
+    let api = data.api.get();
+    let result = api
+        .tx()
+        .dex()
+        .remove_liquidity(
+            req.exchange_id,
+            to,
+            req.asset_ids.clone(),
+            req.liquidities.clone(),
+            req.min_currencies.clone(),
+            req.min_assets.clone(),
+        )
+        .sign_and_submit_then_watch(&signer)
+        .await
+        .map_err(data.nonces.map_err(&signer))?;
+    let result = match tx::wait(&data, &claims.sub, &signer, result, query.wait).await? {
+        tx::Submitted::Done(events) => events,
+        tx::Submitted::Pending(job) => return Ok(HttpResponse::Accepted().json(job)),
+    };
+    let result = result
+        .find_first_event::<sugarfunge::dex::events::LiquidityRemoved>()
+        .map_err(map_subxt_err)?;
+    match result {
+        Some(event) => Ok(HttpResponse::Ok().json(RemoveLiquidityOutput {
+            exchange_id: event.exchange_id,
+            who: event.who.to_string(),
+            to: event.to.to_string(),
+            asset_ids: event.asset_ids,
+            asset_amounts: event.asset_amounts,
+            currency_amounts: event.currency_amounts,
+        })),
+        None => Ok(HttpResponse::BadRequest().json(RequestError {
+            message: json!("Failed to find sugarfunge::dex::events::CurrencyToAsset"),
+        })),
     }
 }
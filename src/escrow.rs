@@ -2,12 +2,14 @@ use crate::state::*;
 use crate::sugarfunge;
 use crate::util::*;
 use crate::user;
-use crate::config::Config;
+use crate::policy;
+use crate::signer;
+use crate::tx;
 use actix_web::{error, web, HttpResponse};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use sp_core::Pair;
 use std::str::FromStr;
-use subxt::PairSigner;
 use actix_web_middleware_keycloak_auth::KeycloakClaims;
 
 
@@ -26,54 +28,38 @@ pub struct CreateEscrowOutput {
 pub async fn create_escrow(
     data: web::Data<AppState>,
     req: web::Json<CreateEscrowInput>,
+    query: web::Query<tx::WaitQuery>,
     claims: KeycloakClaims<user::ClaimsWithEmail>,
-    env: web::Data<Config>
 ) -> error::Result<HttpResponse> {
-    match user::get_seed(&claims.sub, env).await {
-        Ok(response) => {
-            if !response.seed.clone().unwrap_or_default().is_empty() {
-                let user_seed = response.seed.clone().unwrap();
-
-                let pair = get_pair_from_seed(&user_seed)?;
-                let signer = PairSigner::new(pair);
-                let to = sp_core::sr25519::Public::from_str(&req.owner).map_err(map_account_err)?;
-                let to = sp_core::crypto::AccountId32::from(to);
-                let api = data.api.lock().unwrap();
-                let result = api
-                .tx()
-                .escrow()
-                .create_escrow(
-                    to,
-                )
-                .sign_and_submit_then_watch(&signer)
-                    .await
-                    .map_err(map_subxt_err)?
-                    .wait_for_finalized_success()
-                    .await
-                    .map_err(map_subxt_err)?;
-                let result = result
-                    .find_first_event::<sugarfunge::escrow::events::Created>()
-                    .map_err(map_subxt_err)?;
-                match result {
-                    Some(event) => Ok(HttpResponse::Ok().json(CreateEscrowOutput {
-                        escrow: event.escrow.to_string(),
-                        operator: event.operator.to_string(),
-                        owner: event.owner.to_string(),
-                    })),
-                    None => Ok(HttpResponse::BadRequest().json(RequestError {
-                        message: json!("Failed to find sugarfunge::escrow::events::Created"),
-                    })),
-                }
-
-            } else {
-                Ok(HttpResponse::BadRequest().json(RequestError {
-                    message: json!("Not found user Attributes"),
-                }))
-            }
-        },
-        Err(_) => Ok(HttpResponse::BadRequest().json(RequestError {
-            message: json!("Failed to find user::getAttributes"),
-        }))
+    let to = sp_core::sr25519::Public::from_str(&req.owner).map_err(map_account_err)?;
+    let to = sp_core::crypto::AccountId32::from(to);
+    let signer = signer::pair_signer(&data, &claims.sub).await?;
+    let api = data.api.get();
+    let result = api
+    .tx()
+    .escrow()
+    .create_escrow(
+        to,
+    )
+    .sign_and_submit_then_watch(&signer)
+        .await
+        .map_err(data.nonces.map_err(&signer))?;
+    let result = match tx::wait(&data, &claims.sub, &signer, result, query.wait).await? {
+        tx::Submitted::Done(events) => events,
+        tx::Submitted::Pending(job) => return Ok(HttpResponse::Accepted().json(job)),
+    };
+    let result = result
+        .find_first_event::<sugarfunge::escrow::events::Created>()
+        .map_err(map_subxt_err)?;
+    match result {
+        Some(event) => Ok(HttpResponse::Ok().json(CreateEscrowOutput {
+            escrow: event.escrow.to_string(),
+            operator: event.operator.to_string(),
+            owner: event.owner.to_string(),
+        })),
+        None => Ok(HttpResponse::BadRequest().json(RequestError {
+            message: json!("Failed to find sugarfunge::escrow::events::Created"),
+        })),
     }
 }
 
@@ -92,54 +78,38 @@ pub struct RefundAssetsOutput {
 pub async fn refund_assets(
     data: web::Data<AppState>,
     req: web::Json<RefundAssetsInput>,
+    query: web::Query<tx::WaitQuery>,
     claims: KeycloakClaims<user::ClaimsWithEmail>,
-    env: web::Data<Config>
 ) -> error::Result<HttpResponse> {
-    match user::get_seed(&claims.sub, env).await {
-        Ok(response) => {
-            if !response.seed.clone().unwrap_or_default().is_empty() {
-                let user_seed = response.seed.clone().unwrap();
-
-                let pair = get_pair_from_seed(&user_seed)?;
-                let signer = PairSigner::new(pair);
-                let to = sp_core::sr25519::Public::from_str(&req.escrow).map_err(map_account_err)?;
-                let to = sp_core::crypto::AccountId32::from(to);
-                let api = data.api.lock().unwrap();
-                let result = api
-                .tx()
-                .escrow()
-                .refund_assets(
-                    to,
-                )
-                .sign_and_submit_then_watch(&signer)
-                    .await
-                    .map_err(map_subxt_err)?
-                    .wait_for_finalized_success()
-                    .await
-                    .map_err(map_subxt_err)?;
-                let result = result
-                    .find_first_event::<sugarfunge::escrow::events::Refund>()
-                    .map_err(map_subxt_err)?;
-                match result {
-                    Some(event) => Ok(HttpResponse::Ok().json(RefundAssetsOutput {
-                        escrow: event.escrow.to_string(),
-                        operator: event.operator.to_string(),
-                        owner: event.owner.to_string(),
-                    })),
-                    None => Ok(HttpResponse::BadRequest().json(RequestError {
-                        message: json!("Failed to find sugarfunge::escrow::events::Refund"),
-                    })),
-                }
-
-            } else {
-                Ok(HttpResponse::BadRequest().json(RequestError {
-                    message: json!("Not found user Attributes"),
-                }))
-            }
-        },
-        Err(_) => Ok(HttpResponse::BadRequest().json(RequestError {
-            message: json!("Failed to find user::getAttributes"),
-        }))
+    let to = sp_core::sr25519::Public::from_str(&req.escrow).map_err(map_account_err)?;
+    let to = sp_core::crypto::AccountId32::from(to);
+    let signer = signer::pair_signer(&data, &claims.sub).await?;
+    let api = data.api.get();
+    let result = api
+    .tx()
+    .escrow()
+    .refund_assets(
+        to,
+    )
+    .sign_and_submit_then_watch(&signer)
+        .await
+        .map_err(data.nonces.map_err(&signer))?;
+    let result = match tx::wait(&data, &claims.sub, &signer, result, query.wait).await? {
+        tx::Submitted::Done(events) => events,
+        tx::Submitted::Pending(job) => return Ok(HttpResponse::Accepted().json(job)),
+    };
+    let result = result
+        .find_first_event::<sugarfunge::escrow::events::Refund>()
+        .map_err(map_subxt_err)?;
+    match result {
+        Some(event) => Ok(HttpResponse::Ok().json(RefundAssetsOutput {
+            escrow: event.escrow.to_string(),
+            operator: event.operator.to_string(),
+            owner: event.owner.to_string(),
+        })),
+        None => Ok(HttpResponse::BadRequest().json(RequestError {
+            message: json!("Failed to find sugarfunge::escrow::events::Refund"),
+        })),
     }
 }
 
@@ -161,56 +131,43 @@ pub struct DepositAssetsOutput {
 pub async fn deposit_assets(
     data: web::Data<AppState>,
     req: web::Json<DepositAssetsInput>,
+    query: web::Query<tx::WaitQuery>,
     claims: KeycloakClaims<user::ClaimsWithEmail>,
-    env: web::Data<Config>
 ) -> error::Result<HttpResponse> {
-    match user::get_seed(&claims.sub, env).await {
-        Ok(response) => {
-            if !response.seed.clone().unwrap_or_default().is_empty() {
-                let user_seed = response.seed.clone().unwrap();
-
-                let pair = get_pair_from_seed(&user_seed)?;
-                let signer = PairSigner::new(pair);
-                let to = sp_core::sr25519::Public::from_str(&req.escrow).map_err(map_account_err)?;
-                let to = sp_core::crypto::AccountId32::from(to);
-                let api = data.api.lock().unwrap();
-                let result = api
-                .tx()
-                .escrow()
-                .deposit_assets(
-                    to,
-                    req.class_id,
-                    req.asset_ids.clone(),
-                    req.amounts.clone(),
-                )
-                .sign_and_submit_then_watch(&signer)
-                    .await
-                    .map_err(map_subxt_err)?
-                    .wait_for_finalized_success()
-                    .await
-                    .map_err(map_subxt_err)?;
-                let result = result
-                    .find_first_event::<sugarfunge::escrow::events::Deposit>()
-                    .map_err(map_subxt_err)?;
-                match result {
-                    Some(event) => Ok(HttpResponse::Ok().json(DepositAssetsOutput {
-                        escrow: event.escrow.to_string(),
-                        operator: event.operator.to_string(),
-                        owner: event.owner.to_string(),
-                    })),
-                    None => Ok(HttpResponse::BadRequest().json(RequestError {
-                        message: json!("Failed to find sugarfunge::escrow::events::Deposit"),
-                    })),
-                }
-
-            } else {
-                Ok(HttpResponse::BadRequest().json(RequestError {
-                    message: json!("Not found user Attributes"),
-                }))
-            }
-        },
-        Err(_) => Ok(HttpResponse::BadRequest().json(RequestError {
-            message: json!("Failed to find user::getAttributes"),
-        }))
+    let to = sp_core::sr25519::Public::from_str(&req.escrow).map_err(map_account_err)?;
+    let to = sp_core::crypto::AccountId32::from(to);
+    let pair = signer::user_pair(&data, &claims.sub).await?;
+    let who = sp_core::crypto::AccountId32::from(pair.public());
+    let api = data.api.get();
+    policy::check_transfer(&api, req.class_id, &who).await?;
+    let signer = signer::tx_signer(&data, pair).await?;
+    let result = api
+    .tx()
+    .escrow()
+    .deposit_assets(
+        to,
+        req.class_id,
+        req.asset_ids.clone(),
+        req.amounts.clone(),
+    )
+    .sign_and_submit_then_watch(&signer)
+        .await
+        .map_err(data.nonces.map_err(&signer))?;
+    let result = match tx::wait(&data, &claims.sub, &signer, result, query.wait).await? {
+        tx::Submitted::Done(events) => events,
+        tx::Submitted::Pending(job) => return Ok(HttpResponse::Accepted().json(job)),
+    };
+    let result = result
+        .find_first_event::<sugarfunge::escrow::events::Deposit>()
+        .map_err(map_subxt_err)?;
+    match result {
+        Some(event) => Ok(HttpResponse::Ok().json(DepositAssetsOutput {
+            escrow: event.escrow.to_string(),
+            operator: event.operator.to_string(),
+            owner: event.owner.to_string(),
+        })),
+        None => Ok(HttpResponse::BadRequest().json(RequestError {
+            message: json!("Failed to find sugarfunge::escrow::events::Deposit"),
+        })),
     }
 }
@@ -0,0 +1,486 @@
+use crate::db::{unix_time, Database};
+use crate::util::*;
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::{header, Method, StatusCode};
+use actix_web::{error, web, HttpResponse};
+use async_trait::async_trait;
+use futures::future::{ready, LocalBoxFuture, Ready};
+use futures::StreamExt;
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::any::AnyPool;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+pub const IDEMPOTENCY_KEY: &str = "Idempotency-Key";
+pub const IDEMPOTENT_REPLAYED: &str = "Idempotent-Replayed";
+
+/// Largest body of a request sent with an `Idempotency-Key`
+const MAX_BODY: usize = 4 * 1024 * 1024;
+/// Time a request holds its key before a retry can take it over, in case the
+/// request never completes. Longer than a transaction waiting for finalization.
+const IN_FLIGHT_LEASE: Duration = Duration::from_secs(300);
+
+#[derive(Clone, Debug)]
+pub struct StoredResponse {
+    pub status: u16,
+    pub content_type: Option<String>,
+    pub body: Vec<u8>,
+}
+
+pub enum Begin {
+    /// First request with this key, the caller must `complete` or `release` it
+    New,
+    /// A request with this key is still being processed, within its lease
+    InFlight,
+    /// Response of the first request with this key
+    Done(StoredResponse),
+    /// The first request with this key had a different body
+    Mismatch,
+}
+
+/// Storage of the responses for `Idempotency-Key` requests
+#[async_trait(?Send)]
+pub trait IdempotencyStore: Send + Sync {
+    /// Start a request with `key`, `request_hash` identifies its body. The key is
+    /// held for `lease`, after that a request with the same body takes it over.
+    async fn begin(&self, key: &str, request_hash: &str, ttl: Duration, lease: Duration) -> Result<Begin, String>;
+    async fn complete(&self, key: &str, response: StoredResponse) -> Result<(), String>;
+    async fn release(&self, key: &str) -> Result<(), String>;
+}
+
+struct MemoryEntry {
+    created: Instant,
+    locked_until: Instant,
+    request_hash: String,
+    response: Option<StoredResponse>,
+}
+
+#[derive(Default)]
+pub struct MemoryStore {
+    entries: Mutex<HashMap<String, MemoryEntry>>,
+}
+
+#[async_trait(?Send)]
+impl IdempotencyStore for MemoryStore {
+    async fn begin(&self, key: &str, request_hash: &str, ttl: Duration, lease: Duration) -> Result<Begin, String> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, entry| entry.created.elapsed() < ttl);
+        let now = Instant::now();
+        match entries.get(key) {
+            Some(entry) if entry.request_hash != request_hash => Ok(Begin::Mismatch),
+            Some(MemoryEntry {
+                response: Some(response),
+                ..
+            }) => Ok(Begin::Done(response.clone())),
+            Some(entry) if entry.locked_until > now => Ok(Begin::InFlight),
+            _ => {
+                let entry = MemoryEntry {
+                    created: now,
+                    locked_until: now + lease,
+                    request_hash: request_hash.to_string(),
+                    response: None,
+                };
+                entries.insert(key.to_string(), entry);
+                Ok(Begin::New)
+            }
+        }
+    }
+
+    async fn complete(&self, key: &str, response: StoredResponse) -> Result<(), String> {
+        if let Some(entry) = self.entries.lock().unwrap().get_mut(key) {
+            entry.response = Some(response);
+        }
+        Ok(())
+    }
+
+    async fn release(&self, key: &str) -> Result<(), String> {
+        self.entries.lock().unwrap().remove(key);
+        Ok(())
+    }
+}
+
+/// Idempotency keys stored in the `--db-uri` database
+pub struct SqlStore {
+    pool: AnyPool,
+}
+
+impl SqlStore {
+    pub fn new(db: &Database) -> Self {
+        SqlStore {
+            pool: db.pool().clone(),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl IdempotencyStore for SqlStore {
+    async fn begin(&self, key: &str, request_hash: &str, ttl: Duration, lease: Duration) -> Result<Begin, String> {
+        let now = unix_time();
+        let locked_until = now + lease.as_secs() as i64;
+        sqlx::query("DELETE FROM idempotency_keys WHERE created_at < $1")
+            .bind(now - ttl.as_secs() as i64)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| e.to_string())?;
+        let inserted = sqlx::query(
+            "INSERT INTO idempotency_keys (key, request_hash, created_at, locked_until) VALUES ($1, $2, $3, $4)",
+        )
+        .bind(key)
+        .bind(request_hash)
+        .bind(now)
+        .bind(locked_until)
+        .execute(&self.pool)
+        .await;
+        if inserted.is_ok() {
+            return Ok(Begin::New);
+        }
+        // Take over a key whose request never completed, one retry wins the update.
+        // Keys stored before leases have no `locked_until` and are taken over.
+        let taken = sqlx::query(
+            "UPDATE idempotency_keys SET created_at = $1, locked_until = $2
+            WHERE key = $3 AND request_hash = $4 AND status IS NULL
+                AND (locked_until IS NULL OR locked_until <= $1)",
+        )
+        .bind(now)
+        .bind(locked_until)
+        .bind(key)
+        .bind(request_hash)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| e.to_string())?;
+        if taken.rows_affected() == 1 {
+            return Ok(Begin::New);
+        }
+        let row: Option<(Option<String>, Option<i32>, Option<String>, Option<String>)> = sqlx::query_as(
+            "SELECT request_hash, status, content_type, body FROM idempotency_keys WHERE key = $1",
+        )
+        .bind(key)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| e.to_string())?;
+        match row {
+            Some((stored_hash, _, _, _)) if stored_hash.as_deref() != Some(request_hash) => Ok(Begin::Mismatch),
+            Some((_, Some(status), content_type, body)) => Ok(Begin::Done(StoredResponse {
+                status: status as u16,
+                content_type,
+                body: body.unwrap_or_default().into_bytes(),
+            })),
+            Some(_) => Ok(Begin::InFlight),
+            None => Err(inserted.unwrap_err().to_string()),
+        }
+    }
+
+    async fn complete(&self, key: &str, response: StoredResponse) -> Result<(), String> {
+        sqlx::query("UPDATE idempotency_keys SET status = $1, content_type = $2, body = $3 WHERE key = $4")
+            .bind(response.status as i32)
+            .bind(response.content_type)
+            .bind(String::from_utf8_lossy(&response.body).to_string())
+            .bind(key)
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+
+    async fn release(&self, key: &str) -> Result<(), String> {
+        sqlx::query("DELETE FROM idempotency_keys WHERE key = $1")
+            .bind(key)
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenSubject {
+    sub: String,
+}
+
+/// Subject of the bearer token, already validated by the Keycloak middleware
+fn token_subject(req: &ServiceRequest) -> Option<String> {
+    let token = req
+        .headers()
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")?;
+    let payload = token.split('.').nth(1)?;
+    let payload = base64::decode_config(payload, base64::URL_SAFE_NO_PAD).ok()?;
+    let subject: TokenSubject = serde_json::from_slice(&payload).ok()?;
+    Some(subject.sub)
+}
+
+/// Key of the request in the store, scoped by user and route
+fn request_key(req: &ServiceRequest) -> Option<String> {
+    if req.method() != Method::POST {
+        return None;
+    }
+    let key = req.headers().get(IDEMPOTENCY_KEY)?.to_str().ok()?;
+    let subject = token_subject(req).unwrap_or_default();
+    Some(format!("{}:{}:{}", subject, req.path(), key))
+}
+
+/// Identifies the body of a request, so that a key is not reused for another request
+fn request_hash(body: &[u8]) -> String {
+    hex::encode(sp_core::hashing::sha2_256(body))
+}
+
+/// Read the whole body of the request and put it back for the handler
+async fn read_body(req: &mut ServiceRequest) -> Result<web::Bytes, actix_web::Error> {
+    let mut payload = req.take_payload();
+    let mut body = web::BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk?;
+        if body.len() + chunk.len() > MAX_BODY {
+            return Err(error::ErrorPayloadTooLarge("Request body is too large"));
+        }
+        body.extend_from_slice(&chunk);
+    }
+    let body = body.freeze();
+    let (_, mut replay) = actix_http::h1::Payload::create(true);
+    replay.unread_data(body.clone());
+    req.set_payload(replay.into());
+    Ok(body)
+}
+
+/// Whether a response is replayed to later requests with the same key. Server
+/// errors are retried, and so are authorization errors: the admin role of
+/// some routes is checked after this middleware, a caller granted the role
+/// later must not get the `403` of its first attempt.
+fn is_stored(status: StatusCode) -> bool {
+    !status.is_server_error() && status != StatusCode::UNAUTHORIZED && status != StatusCode::FORBIDDEN
+}
+
+fn map_store_err(e: String) -> actix_web::Error {
+    let req_error = RequestError {
+        message: json!(format!("Idempotency store error: {}", e)),
+    };
+    let req_error = serde_json::to_string_pretty(&req_error).unwrap();
+    error::ErrorInternalServerError(req_error)
+}
+
+/// Replays the first response of POST requests sent with the same `Idempotency-Key` header
+pub struct Idempotency {
+    store: Arc<dyn IdempotencyStore>,
+    ttl: Duration,
+}
+
+impl Idempotency {
+    pub fn new(store: Arc<dyn IdempotencyStore>, ttl: Duration) -> Self {
+        Idempotency { store, ttl }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for Idempotency
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = actix_web::Error;
+    type Transform = IdempotencyMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(IdempotencyMiddleware {
+            service: Rc::new(service),
+            store: self.store.clone(),
+            ttl: self.ttl,
+        }))
+    }
+}
+
+pub struct IdempotencyMiddleware<S> {
+    service: Rc<S>,
+    store: Arc<dyn IdempotencyStore>,
+    ttl: Duration,
+}
+
+impl<S, B> Service<ServiceRequest> for IdempotencyMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let store = self.store.clone();
+        let ttl = self.ttl;
+
+        Box::pin(async move {
+            let key = match request_key(&req) {
+                Some(key) => key,
+                None => return service.call(req).await.map(|res| res.map_into_boxed_body()),
+            };
+
+            let body = read_body(&mut req).await?;
+            match store.begin(&key, &request_hash(&body), ttl, IN_FLIGHT_LEASE).await.map_err(map_store_err)? {
+                Begin::Mismatch => {
+                    let response = HttpResponse::UnprocessableEntity().json(RequestError {
+                        message: json!("Idempotency-Key was already used with a different request body"),
+                    });
+                    return Ok(req.into_response(response));
+                }
+                Begin::InFlight => {
+                    let response = HttpResponse::Conflict().json(RequestError {
+                        message: json!("A request with this Idempotency-Key is in progress"),
+                    });
+                    return Ok(req.into_response(response));
+                }
+                Begin::Done(stored) => {
+                    let status = StatusCode::from_u16(stored.status).unwrap_or(StatusCode::OK);
+                    let mut response = HttpResponse::build(status);
+                    if let Some(content_type) = stored.content_type {
+                        response.insert_header((header::CONTENT_TYPE, content_type));
+                    }
+                    let response = response
+                        .insert_header((IDEMPOTENT_REPLAYED, "true"))
+                        .body(stored.body);
+                    return Ok(req.into_response(response));
+                }
+                Begin::New => {}
+            }
+
+            let res = match service.call(req).await {
+                Ok(res) => res,
+                Err(e) => {
+                    store.release(&key).await.map_err(map_store_err)?;
+                    return Err(e);
+                }
+            };
+            if !is_stored(res.status()) {
+                store.release(&key).await.map_err(map_store_err)?;
+                return Ok(res.map_into_boxed_body());
+            }
+
+            let (req, res) = res.into_parts();
+            let (res, body) = res.into_parts();
+            let body = actix_web::body::to_bytes(body).await.map_err(|e| {
+                let e: Box<dyn std::error::Error> = e.into();
+                error::ErrorInternalServerError(e.to_string())
+            })?;
+            let stored = StoredResponse {
+                status: res.status().as_u16(),
+                content_type: res
+                    .headers()
+                    .get(header::CONTENT_TYPE)
+                    .and_then(|value| value.to_str().ok())
+                    .map(|value| value.to_string()),
+                body: body.to_vec(),
+            };
+            store.complete(&key, stored).await.map_err(map_store_err)?;
+
+            Ok(ServiceResponse::new(req, res.set_body(BoxBody::new(body))))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn bearer(sub: &str) -> String {
+        let payload = base64::encode_config(json!({ "sub": sub }).to_string(), base64::URL_SAFE_NO_PAD);
+        format!("Bearer e30.{}.signature", payload)
+    }
+
+    #[test]
+    fn request_key_is_scoped_by_user_and_route() {
+        let req = TestRequest::post()
+            .uri("/asset/mint")
+            .insert_header((IDEMPOTENCY_KEY, "key-1"))
+            .insert_header((header::AUTHORIZATION, bearer("alice")))
+            .to_srv_request();
+        assert_eq!(request_key(&req).as_deref(), Some("alice:/asset/mint:key-1"));
+
+        let req = TestRequest::post()
+            .uri("/asset/burn")
+            .insert_header((IDEMPOTENCY_KEY, "key-1"))
+            .insert_header((header::AUTHORIZATION, bearer("bob")))
+            .to_srv_request();
+        assert_eq!(request_key(&req).as_deref(), Some("bob:/asset/burn:key-1"));
+    }
+
+    #[test]
+    fn request_key_requires_post_and_header() {
+        let req = TestRequest::post().uri("/asset/mint").to_srv_request();
+        assert_eq!(request_key(&req), None);
+
+        let req = TestRequest::get()
+            .uri("/asset/mint")
+            .insert_header((IDEMPOTENCY_KEY, "key-1"))
+            .to_srv_request();
+        assert_eq!(request_key(&req), None);
+    }
+
+    #[test]
+    fn request_hash_depends_on_body() {
+        assert_eq!(request_hash(b"{\"amount\":1}"), request_hash(b"{\"amount\":1}"));
+        assert_ne!(request_hash(b"{\"amount\":1}"), request_hash(b"{\"amount\":2}"));
+    }
+
+    #[test]
+    fn authorization_errors_are_not_stored() {
+        assert!(is_stored(StatusCode::OK));
+        assert!(is_stored(StatusCode::ACCEPTED));
+        assert!(is_stored(StatusCode::BAD_REQUEST));
+        assert!(is_stored(StatusCode::CONFLICT));
+        assert!(!is_stored(StatusCode::UNAUTHORIZED));
+        assert!(!is_stored(StatusCode::FORBIDDEN));
+        assert!(!is_stored(StatusCode::INTERNAL_SERVER_ERROR));
+        assert!(!is_stored(StatusCode::BAD_GATEWAY));
+    }
+
+    #[actix_web::test]
+    async fn memory_store_rejects_other_body() {
+        let store = MemoryStore::default();
+        let (ttl, lease) = (Duration::from_secs(60), Duration::from_secs(60));
+        let (first, second) = (request_hash(b"first"), request_hash(b"second"));
+        assert!(matches!(store.begin("key", &first, ttl, lease).await, Ok(Begin::New)));
+        assert!(matches!(store.begin("key", &first, ttl, lease).await, Ok(Begin::InFlight)));
+        assert!(matches!(store.begin("key", &second, ttl, lease).await, Ok(Begin::Mismatch)));
+
+        let response = StoredResponse {
+            status: 200,
+            content_type: None,
+            body: b"done".to_vec(),
+        };
+        store.complete("key", response).await.unwrap();
+        assert!(matches!(store.begin("key", &first, ttl, lease).await, Ok(Begin::Done(stored)) if stored.body == b"done"));
+        assert!(matches!(store.begin("key", &second, ttl, lease).await, Ok(Begin::Mismatch)));
+
+        store.release("key").await.unwrap();
+        assert!(matches!(store.begin("key", &second, ttl, lease).await, Ok(Begin::New)));
+    }
+
+    #[actix_web::test]
+    async fn memory_store_takes_over_expired_lease() {
+        let store = MemoryStore::default();
+        let ttl = Duration::from_secs(60);
+        let (first, second) = (request_hash(b"first"), request_hash(b"second"));
+        assert!(matches!(store.begin("key", &first, ttl, Duration::ZERO).await, Ok(Begin::New)));
+        assert!(matches!(store.begin("key", &second, ttl, Duration::ZERO).await, Ok(Begin::Mismatch)));
+        assert!(matches!(store.begin("key", &first, ttl, ttl).await, Ok(Begin::New)));
+        assert!(matches!(store.begin("key", &first, ttl, ttl).await, Ok(Begin::InFlight)));
+
+        let response = StoredResponse {
+            status: 200,
+            content_type: None,
+            body: b"done".to_vec(),
+        };
+        store.complete("key", response).await.unwrap();
+        assert!(matches!(store.begin("key", &first, ttl, Duration::ZERO).await, Ok(Begin::Done(_))));
+    }
+}
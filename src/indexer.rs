@@ -0,0 +1,662 @@
+use crate::db::{BalanceChange, Database, NewEvent, SupplyChange};
+use crate::state::*;
+use crate::sugarfunge;
+use serde::Serialize;
+use sp_core::crypto::AccountId32;
+use std::time::Duration;
+use sugarfunge::runtime_types::frame_system::Phase;
+use sugarfunge::runtime_types::pallet_balances::pallet::Event as BalancesEvent;
+use sugarfunge::runtime_types::sugarfunge_asset::pallet::Event as AssetEvent;
+use sugarfunge::runtime_types::sugarfunge_bundle::pallet::Event as BundleEvent;
+use sugarfunge::runtime_types::sugarfunge_currency::pallet::Event as CurrencyEvent;
+use sugarfunge::runtime_types::sugarfunge_dex::pallet::Event as DexEvent;
+use sugarfunge::runtime_types::sugarfunge_escrow::pallet::Event as EscrowEvent;
+use sugarfunge::runtime_types::sugarfunge_runtime::Event;
+
+/// Delay before resubscribing after the node connection fails
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Index finalized blocks into `db`, starting after the last indexed block or at `from`
+pub async fn run(api: ClientAPI, db: Database, from: u32) {
+    loop {
+        if let Err(e) = index(&api, &db, from).await {
+            log::error!("Indexer stopped: {}", e);
+        }
+        actix_web::rt::time::sleep(RETRY_DELAY).await;
+    }
+}
+
+/// Check that indexing can start at `from`: a database that already has indexed
+/// blocks resumes after the last one, skipping blocks would leave a gap in the events
+/// and the balances.
+pub async fn check_start(db: &Database, from: u32) -> Result<(), String> {
+    match db.last_indexed_block().await.map_err(|e| e.to_string())? {
+        Some(last) if from > last + 1 => Err(format!(
+            "--index-from #{} is past the next block to index #{}, blocks #{} to #{} would be skipped. \
+            Start with an empty database to index from #{}",
+            from,
+            last + 1,
+            last + 1,
+            from - 1,
+            from
+        )),
+        _ => Ok(()),
+    }
+}
+
+async fn index(api: &RuntimeApi, db: &Database, from: u32) -> Result<(), String> {
+    let mut next = match db.last_indexed_block().await.map_err(|e| e.to_string())? {
+        Some(last) => last + 1,
+        None => from,
+    };
+    log::info!("Indexing finalized blocks from #{}", next);
+
+    let mut headers = api
+        .client
+        .rpc()
+        .subscribe_finalized_blocks()
+        .await
+        .map_err(|e| e.to_string())?;
+    while let Some(header) = headers.next().await.map_err(|e| e.to_string())? {
+        while next <= header.number {
+            index_block(api, db, next).await?;
+            next += 1;
+        }
+    }
+    Err("Finalized blocks subscription closed".to_string())
+}
+
+async fn index_block(api: &RuntimeApi, db: &Database, number: u32) -> Result<(), String> {
+    let hash = api
+        .client
+        .rpc()
+        .block_hash(Some(number.into()))
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Block #{} not found", number))?;
+    let records = api
+        .storage()
+        .system()
+        .events(Some(hash))
+        .await
+        .map_err(|e| e.to_string())?;
+    let timestamp = api
+        .storage()
+        .timestamp()
+        .now(Some(hash))
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let events: Vec<NewEvent> = records
+        .into_iter()
+        .enumerate()
+        .filter_map(|(event_index, record)| {
+            let extrinsic_index = match record.phase {
+                Phase::ApplyExtrinsic(index) => Some(index),
+                _ => None,
+            };
+            decode(record.event).map(|event| NewEvent {
+                extrinsic_index,
+                event_index: event_index as u32,
+                ..event
+            })
+        })
+        .collect();
+
+    db.save_block_events(number, &format!("{:?}", hash), Some(timestamp), &events)
+        .await
+        .map_err(|e| e.to_string())?;
+    if !events.is_empty() {
+        log::debug!("Indexed {} events of block #{}", events.len(), number);
+    }
+    Ok(())
+}
+
+fn new_event<T: Serialize>(pallet: &str, variant: &str, accounts: &[&AccountId32], data: T) -> NewEvent {
+    NewEvent {
+        extrinsic_index: None,
+        event_index: 0,
+        pallet: pallet.to_string(),
+        variant: variant.to_string(),
+        class_id: None,
+        asset_id: None,
+        accounts: accounts.iter().map(|account| account.to_string()).collect(),
+        data: serde_json::to_string(&data).unwrap(),
+        supply: vec![],
+        balances: vec![],
+    }
+}
+
+/// Supply changes of a mint (`minted`) or burn of `amounts` of `asset_ids`
+fn supply_changes(class_id: u64, asset_ids: &[u64], amounts: &[u128], minted: bool) -> Vec<SupplyChange> {
+    asset_ids
+        .iter()
+        .zip(amounts)
+        .map(|(asset_id, amount)| SupplyChange {
+            class_id,
+            asset_id: *asset_id,
+            minted: if minted { *amount } else { 0 },
+            burned: if minted { 0 } else { *amount },
+        })
+        .collect()
+}
+
+/// Balance changes of moving `amounts` of `asset_ids` out of `from` and into `to`,
+/// a mint has no `from` and a burn no `to`
+fn balance_changes(
+    class_id: u64,
+    asset_ids: &[u64],
+    amounts: &[u128],
+    from: Option<&AccountId32>,
+    to: Option<&AccountId32>,
+) -> Vec<BalanceChange> {
+    let mut changes = vec![];
+    for (asset_id, amount) in asset_ids.iter().zip(amounts) {
+        if let Some(from) = from {
+            changes.push(BalanceChange {
+                class_id,
+                asset_id: *asset_id,
+                account: from.to_string(),
+                credit: 0,
+                debit: *amount,
+            });
+        }
+        if let Some(to) = to {
+            changes.push(BalanceChange {
+                class_id,
+                asset_id: *asset_id,
+                account: to.to_string(),
+                credit: *amount,
+                debit: 0,
+            });
+        }
+    }
+    changes
+}
+
+fn with_asset(event: NewEvent, class_id: u64, asset_id: Option<u64>) -> NewEvent {
+    NewEvent {
+        class_id: Some(class_id),
+        asset_id,
+        ..event
+    }
+}
+
+#[derive(Serialize)]
+struct ClassCreatedData {
+    class_id: u64,
+    who: String,
+}
+
+#[derive(Serialize)]
+struct AssetCreatedData {
+    class_id: u64,
+    asset_id: u64,
+    who: String,
+}
+
+#[derive(Serialize)]
+struct MintData {
+    to: String,
+    class_id: u64,
+    asset_id: u64,
+    amount: u128,
+    who: String,
+}
+
+#[derive(Serialize)]
+struct BatchMintData {
+    to: String,
+    class_id: u64,
+    asset_ids: Vec<u64>,
+    amounts: Vec<u128>,
+    who: String,
+}
+
+#[derive(Serialize)]
+struct BurnData {
+    from: String,
+    class_id: u64,
+    asset_id: u64,
+    amount: u128,
+    who: String,
+}
+
+#[derive(Serialize)]
+struct BatchBurnData {
+    from: String,
+    class_id: u64,
+    asset_ids: Vec<u64>,
+    amounts: Vec<u128>,
+    who: String,
+}
+
+#[derive(Serialize)]
+struct TransferredData {
+    from: String,
+    to: String,
+    class_id: u64,
+    asset_id: u64,
+    amount: u128,
+    who: String,
+}
+
+#[derive(Serialize)]
+struct BatchTransferredData {
+    from: String,
+    to: String,
+    class_id: u64,
+    asset_ids: Vec<u64>,
+    amounts: Vec<u128>,
+    who: String,
+}
+
+#[derive(Serialize)]
+struct OperatorApprovalData {
+    who: String,
+    operator: String,
+    class_id: u64,
+    approved: bool,
+}
+
+#[derive(Serialize)]
+struct CurrencyData {
+    class_id: u64,
+    asset_id: u64,
+}
+
+#[derive(Serialize)]
+struct CurrencyAmountData {
+    currency: CurrencyData,
+    amount: u128,
+    who: String,
+}
+
+#[derive(Serialize)]
+struct ExchangeCreatedData {
+    exchange_id: u32,
+    who: String,
+}
+
+#[derive(Serialize)]
+struct CurrencyToAssetData {
+    exchange_id: u32,
+    who: String,
+    to: String,
+    asset_ids: Vec<u64>,
+    asset_amounts_out: Vec<u128>,
+    currency_amounts_in: Vec<u128>,
+}
+
+#[derive(Serialize)]
+struct AssetToCurrencyData {
+    exchange_id: u32,
+    who: String,
+    to: String,
+    asset_ids: Vec<u64>,
+    asset_amounts_in: Vec<u128>,
+    currency_amounts_out: Vec<u128>,
+}
+
+#[derive(Serialize)]
+struct LiquidityData {
+    exchange_id: u32,
+    who: String,
+    to: String,
+    asset_ids: Vec<u64>,
+    asset_amounts: Vec<u128>,
+    currency_amounts: Vec<u128>,
+}
+
+#[derive(Serialize)]
+struct EscrowData {
+    escrow: String,
+    operator: String,
+    owner: String,
+}
+
+#[derive(Serialize)]
+struct RegisterBundleData {
+    bundle_id: String,
+    who: String,
+    class_id: u64,
+    asset_id: u64,
+}
+
+#[derive(Serialize)]
+struct BundleAmountData {
+    who: String,
+    from: String,
+    to: String,
+    bundle_id: String,
+    amount: u128,
+}
+
+#[derive(Serialize)]
+struct EndowedData {
+    account: String,
+    free_balance: u128,
+}
+
+#[derive(Serialize)]
+struct DustLostData {
+    account: String,
+    amount: u128,
+}
+
+#[derive(Serialize)]
+struct TransferData {
+    from: String,
+    to: String,
+    amount: u128,
+}
+
+#[derive(Serialize)]
+struct BalanceSetData {
+    who: String,
+    free: u128,
+    reserved: u128,
+}
+
+#[derive(Serialize)]
+struct BalanceChangeData {
+    who: String,
+    amount: u128,
+}
+
+/// Decode the events of the indexed pallets, with the field names of the handler outputs
+fn decode(event: Event) -> Option<NewEvent> {
+    match event {
+        Event::Asset(event) => Some(decode_asset(event)),
+        Event::Currency(event) => Some(decode_currency(event)),
+        Event::Dex(event) => Some(decode_dex(event)),
+        Event::Escrow(event) => Some(decode_escrow(event)),
+        Event::Bundle(event) => Some(decode_bundle(event)),
+        Event::Balances(event) => Some(decode_balances(event)),
+        _ => None,
+    }
+}
+
+fn decode_asset(event: AssetEvent) -> NewEvent {
+    match event {
+        AssetEvent::ClassCreated { class_id, who } => {
+            let data = ClassCreatedData {
+                class_id,
+                who: who.to_string(),
+            };
+            with_asset(new_event("Asset", "ClassCreated", &[&who], data), class_id, None)
+        }
+        AssetEvent::AssetCreated { class_id, asset_id, who } => {
+            let data = AssetCreatedData {
+                class_id,
+                asset_id,
+                who: who.to_string(),
+            };
+            with_asset(new_event("Asset", "AssetCreated", &[&who], data), class_id, Some(asset_id))
+        }
+        AssetEvent::Mint { who, to, class_id, asset_id, amount } => {
+            let balances = balance_changes(class_id, &[asset_id], &[amount], None, Some(&to));
+            let data = MintData {
+                to: to.to_string(),
+                class_id,
+                asset_id,
+                amount,
+                who: who.to_string(),
+            };
+            let event = with_asset(new_event("Asset", "Mint", &[&who, &to], data), class_id, Some(asset_id));
+            NewEvent {
+                supply: supply_changes(class_id, &[asset_id], &[amount], true),
+                balances,
+                ..event
+            }
+        }
+        AssetEvent::BatchMint { who, to, class_id, asset_ids, amounts } => {
+            let supply = supply_changes(class_id, &asset_ids, &amounts, true);
+            let balances = balance_changes(class_id, &asset_ids, &amounts, None, Some(&to));
+            let data = BatchMintData {
+                to: to.to_string(),
+                class_id,
+                asset_ids,
+                amounts,
+                who: who.to_string(),
+            };
+            let event = with_asset(new_event("Asset", "BatchMint", &[&who, &to], data), class_id, None);
+            NewEvent { supply, balances, ..event }
+        }
+        AssetEvent::Burn { who, from, class_id, asset_id, amount } => {
+            let balances = balance_changes(class_id, &[asset_id], &[amount], Some(&from), None);
+            let data = BurnData {
+                from: from.to_string(),
+                class_id,
+                asset_id,
+                amount,
+                who: who.to_string(),
+            };
+            let event = with_asset(new_event("Asset", "Burn", &[&who, &from], data), class_id, Some(asset_id));
+            NewEvent {
+                supply: supply_changes(class_id, &[asset_id], &[amount], false),
+                balances,
+                ..event
+            }
+        }
+        AssetEvent::BatchBurn { who, from, class_id, asset_ids, amounts } => {
+            let supply = supply_changes(class_id, &asset_ids, &amounts, false);
+            let balances = balance_changes(class_id, &asset_ids, &amounts, Some(&from), None);
+            let data = BatchBurnData {
+                from: from.to_string(),
+                class_id,
+                asset_ids,
+                amounts,
+                who: who.to_string(),
+            };
+            let event = with_asset(new_event("Asset", "BatchBurn", &[&who, &from], data), class_id, None);
+            NewEvent { supply, balances, ..event }
+        }
+        AssetEvent::Transferred { who, from, to, class_id, asset_id, amount } => {
+            let balances = balance_changes(class_id, &[asset_id], &[amount], Some(&from), Some(&to));
+            let data = TransferredData {
+                from: from.to_string(),
+                to: to.to_string(),
+                class_id,
+                asset_id,
+                amount,
+                who: who.to_string(),
+            };
+            let event = new_event("Asset", "Transferred", &[&who, &from, &to], data);
+            NewEvent {
+                balances,
+                ..with_asset(event, class_id, Some(asset_id))
+            }
+        }
+        AssetEvent::BatchTransferred { who, from, to, class_id, asset_ids, amounts } => {
+            let balances = balance_changes(class_id, &asset_ids, &amounts, Some(&from), Some(&to));
+            let data = BatchTransferredData {
+                from: from.to_string(),
+                to: to.to_string(),
+                class_id,
+                asset_ids,
+                amounts,
+                who: who.to_string(),
+            };
+            let event = new_event("Asset", "BatchTransferred", &[&who, &from, &to], data);
+            NewEvent {
+                balances,
+                ..with_asset(event, class_id, None)
+            }
+        }
+        AssetEvent::OperatorApprovalForAll { who, operator, class_id, approved } => {
+            let data = OperatorApprovalData {
+                who: who.to_string(),
+                operator: operator.to_string(),
+                class_id,
+                approved,
+            };
+            let event = new_event("Asset", "OperatorApprovalForAll", &[&who, &operator], data);
+            with_asset(event, class_id, None)
+        }
+    }
+}
+
+fn decode_currency(event: CurrencyEvent) -> NewEvent {
+    let (variant, currency_id, amount, who) = match event {
+        CurrencyEvent::Mint { currency_id, amount, who } => ("Mint", currency_id, amount, who),
+        CurrencyEvent::Burn { currency_id, amount, who } => ("Burn", currency_id, amount, who),
+    };
+    let data = CurrencyAmountData {
+        currency: CurrencyData {
+            class_id: currency_id.0,
+            asset_id: currency_id.1,
+        },
+        amount,
+        who: who.to_string(),
+    };
+    with_asset(new_event("Currency", variant, &[&who], data), currency_id.0, Some(currency_id.1))
+}
+
+fn decode_dex(event: DexEvent) -> NewEvent {
+    match event {
+        DexEvent::ExchangeCreated { exchange_id, who } => {
+            let data = ExchangeCreatedData {
+                exchange_id,
+                who: who.to_string(),
+            };
+            new_event("Dex", "ExchangeCreated", &[&who], data)
+        }
+        DexEvent::CurrencyToAsset { exchange_id, who, to, asset_ids, asset_amounts_out, currency_amounts_in } => {
+            let data = CurrencyToAssetData {
+                exchange_id,
+                who: who.to_string(),
+                to: to.to_string(),
+                asset_ids,
+                asset_amounts_out,
+                currency_amounts_in,
+            };
+            new_event("Dex", "CurrencyToAsset", &[&who, &to], data)
+        }
+        DexEvent::AssetToCurrency { exchange_id, who, to, asset_ids, asset_amounts_in, currency_amounts_out } => {
+            let data = AssetToCurrencyData {
+                exchange_id,
+                who: who.to_string(),
+                to: to.to_string(),
+                asset_ids,
+                asset_amounts_in,
+                currency_amounts_out,
+            };
+            new_event("Dex", "AssetToCurrency", &[&who, &to], data)
+        }
+        DexEvent::LiquidityAdded { exchange_id, who, to, asset_ids, asset_amounts, currency_amounts } => {
+            let data = LiquidityData {
+                exchange_id,
+                who: who.to_string(),
+                to: to.to_string(),
+                asset_ids,
+                asset_amounts,
+                currency_amounts,
+            };
+            new_event("Dex", "LiquidityAdded", &[&who, &to], data)
+        }
+        DexEvent::LiquidityRemoved { exchange_id, who, to, asset_ids, asset_amounts, currency_amounts } => {
+            let data = LiquidityData {
+                exchange_id,
+                who: who.to_string(),
+                to: to.to_string(),
+                asset_ids,
+                asset_amounts,
+                currency_amounts,
+            };
+            new_event("Dex", "LiquidityRemoved", &[&who, &to], data)
+        }
+    }
+}
+
+fn decode_escrow(event: EscrowEvent) -> NewEvent {
+    let (variant, escrow, operator, owner) = match event {
+        EscrowEvent::Created { escrow, operator, owner } => ("Created", escrow, operator, owner),
+        EscrowEvent::Deposit { escrow, operator, owner } => ("Deposit", escrow, operator, owner),
+        EscrowEvent::Refund { escrow, operator, owner } => ("Refund", escrow, operator, owner),
+    };
+    let data = EscrowData {
+        escrow: escrow.to_string(),
+        operator: operator.to_string(),
+        owner: owner.to_string(),
+    };
+    new_event("Escrow", variant, &[&escrow, &operator, &owner], data)
+}
+
+fn decode_bundle(event: BundleEvent) -> NewEvent {
+    let (variant, bundle_id, who, from, to, amount) = match event {
+        BundleEvent::Register { bundle_id, who, class_id, asset_id } => {
+            let data = RegisterBundleData {
+                bundle_id: format!("{:?}", bundle_id),
+                who: who.to_string(),
+                class_id,
+                asset_id,
+            };
+            let event = new_event("Bundle", "Register", &[&who], data);
+            return with_asset(event, class_id, Some(asset_id));
+        }
+        BundleEvent::Mint { bundle_id, who, from, to, amount } => ("Mint", bundle_id, who, from, to, amount),
+        BundleEvent::Burn { bundle_id, who, from, to, amount } => ("Burn", bundle_id, who, from, to, amount),
+    };
+    let data = BundleAmountData {
+        who: who.to_string(),
+        from: from.to_string(),
+        to: to.to_string(),
+        bundle_id: format!("{:?}", bundle_id),
+        amount,
+    };
+    new_event("Bundle", variant, &[&who, &from, &to], data)
+}
+
+fn decode_balances(event: BalancesEvent) -> NewEvent {
+    let (variant, who, amount) = match event {
+        BalancesEvent::Endowed { account, free_balance } => {
+            let data = EndowedData {
+                account: account.to_string(),
+                free_balance,
+            };
+            return new_event("Balances", "Endowed", &[&account], data);
+        }
+        BalancesEvent::DustLost { account, amount } => {
+            let data = DustLostData {
+                account: account.to_string(),
+                amount,
+            };
+            return new_event("Balances", "DustLost", &[&account], data);
+        }
+        BalancesEvent::Transfer { from, to, amount } => {
+            let data = TransferData {
+                from: from.to_string(),
+                to: to.to_string(),
+                amount,
+            };
+            return new_event("Balances", "Transfer", &[&from, &to], data);
+        }
+        BalancesEvent::ReserveRepatriated { from, to, amount, .. } => {
+            let data = TransferData {
+                from: from.to_string(),
+                to: to.to_string(),
+                amount,
+            };
+            return new_event("Balances", "ReserveRepatriated", &[&from, &to], data);
+        }
+        BalancesEvent::BalanceSet { who, free, reserved } => {
+            let data = BalanceSetData {
+                who: who.to_string(),
+                free,
+                reserved,
+            };
+            return new_event("Balances", "BalanceSet", &[&who], data);
+        }
+        BalancesEvent::Reserved { who, amount } => ("Reserved", who, amount),
+        BalancesEvent::Unreserved { who, amount } => ("Unreserved", who, amount),
+        BalancesEvent::Deposit { who, amount } => ("Deposit", who, amount),
+        BalancesEvent::Withdraw { who, amount } => ("Withdraw", who, amount),
+        BalancesEvent::Slashed { who, amount } => ("Slashed", who, amount),
+    };
+    let data = BalanceChangeData {
+        who: who.to_string(),
+        amount,
+    };
+    new_event("Balances", variant, &[&who], data)
+}
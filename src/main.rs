@@ -5,13 +5,15 @@ use actix_web::{
     App, HttpServer,
     http
 };
+use client::*;
 use command::*;
+use idempotency::*;
+use signer::*;
 use state::*;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use structopt::StructOpt;
-use subxt::ClientBuilder;
 use actix_web_middleware_keycloak_auth::{
-    AlwaysReturnPolicy, DecodingKey, KeycloakAuth,
+    AlwaysReturnPolicy, DecodingKey, KeycloakAuth, Role,
 };
 use dotenv::dotenv;
 
@@ -20,19 +22,42 @@ pub mod sugarfunge {}
 mod config;
 mod account;
 mod asset;
+mod audit;
+mod blob;
 mod bundle;
+mod client;
 mod command;
 mod currency;
+mod db;
 mod dex;
 mod escrow;
+mod idempotency;
+mod indexer;
+mod policy;
+mod scale;
+mod schema;
+mod signer;
 mod state;
+mod storage;
+mod sudo;
+mod tx;
 mod util;
 mod user;
+mod vault;
 
 const KEYCLOAK_PK: &str = "-----BEGIN PUBLIC KEY-----
 MIIBIjANBgkqhkiG9w0BAQEFAAOCAQ8AMIIBCgKCAQEAxlFO8ZQyPq86xgeg0mlTvItO2DvQkwmDQ1mBinCqY2IT1+L9Ov0HBPiw65Y77b81CD1XBc01uL8IH1vV5nGg6ESMguw5qASZNyJ4a7y7aRxjP4Gwg+8vqgCSzUq4bwMpMnQI8dXllCLvNskAONkRU9MMFN3nqTyZJcrzUZADN11uzfu6ZovEZJkXla/4hDITVFZP44JjGyr6IBxq3DzN96SPR3lwi+Ip6IsQGWuTHpjAEi1dEOeJhQ29nbvAywnrYikxZlHqrKX1nUmzUu8cF9nVOor/fQK3gCkD0wsndc77K5vNKkyLO3SbCs0IlRjpexX3fgQ/eduDXfSUoUOfeQIDAQAB
 -----END PUBLIC KEY-----";
 
+fn keycloak_auth(required_roles: Vec<Role>) -> KeycloakAuth<AlwaysReturnPolicy> {
+    KeycloakAuth {
+        detailed_responses: true,
+        passthrough_policy: AlwaysReturnPolicy,
+        keycloak_oid_public_key: DecodingKey::from_rsa_pem(KEYCLOAK_PK.as_bytes()).unwrap(),
+        required_roles,
+    }
+}
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     dotenv().ok(); 
@@ -43,15 +68,83 @@ async fn main() -> std::io::Result<()> {
 
     let opt = Opt::from_args();
 
-    let api = ClientBuilder::new()
-        .set_url(opt.node_server.to_string())
-        .build()
+    let tokens = Arc::new(user::TokenManager::new(Data::new(env.clone())));
+
+    if let Some(Command::RotateSeeds) = opt.cmd {
+        let rotated = user::rotate_seeds(&tokens)
+            .await
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+        log::info!("Re-encrypted {} user seeds", rotated);
+        return Ok(());
+    }
+
+    if env.seed_master_keys.is_none() && opt.signer == "keycloak" {
+        log::warn!("SEED_MASTER_KEYS is not set, user seeds are stored in plaintext");
+    }
+
+    let api = ClientPool::connect(&opt.node_server, opt.pool_size)
         .await
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))?
-        .to_runtime_api::<sugarfunge::RuntimeApi<sugarfunge::DefaultConfig>>();
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))?;
+
+    let signer: Arc<dyn SignerBackend> = match opt.signer.as_str() {
+        "keystore" => {
+            let password = env.keystore_password.clone().ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    "KEYSTORE_PASSWORD enviroment variable is not set",
+                )
+            })?;
+            Arc::new(KeystoreSigner::open(opt.keystore.clone(), &password)?)
+        }
+        "dev" => Arc::new(DevSigner::default()),
+        _ => Arc::new(KeycloakSigner::new(tokens.clone())),
+    };
+
+    let db = match &opt.db {
+        Some(uri) => Some(
+            db::Database::connect(uri)
+                .await
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))?,
+        ),
+        None => None,
+    };
+
+    let idempotency_store: Arc<dyn IdempotencyStore> = match &db {
+        Some(db) => Arc::new(SqlStore::new(db)),
+        None => Arc::new(MemoryStore::default()),
+    };
+    let idempotency_ttl = std::time::Duration::from_secs(opt.idempotency_ttl);
+
+    let blobs = match &opt.blob_dir {
+        Some(dir) => {
+            let url = opt
+                .blob_url
+                .clone()
+                .unwrap_or_else(|| format!("{}blob/", opt.listen));
+            Some(Arc::new(blob::BlobStore::open(dir.clone(), url)?))
+        }
+        None => None,
+    };
+
+    let api = Arc::new(api);
+
+    if let Some(db) = &db {
+        indexer::check_start(db, opt.index_from)
+            .await
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+        actix_web::rt::spawn(indexer::run(api.get(), db.clone(), opt.index_from));
+    }
 
     let state = AppState {
-        api: Arc::new(Mutex::new(api)),
+        api,
+        nonces: Arc::new(NonceManager::default()),
+        jobs: Arc::new(tx::JobStore::new(db.clone())),
+        signer,
+        tokens,
+        db,
+        blobs,
+        issuance: Arc::new(tokio::sync::Mutex::new(())),
+        holders_check: Arc::new(tokio::sync::Mutex::new(None)),
     };
 
     HttpServer::new(move || {        
@@ -62,14 +155,13 @@ async fn main() -> std::io::Result<()> {
             })
             .allowed_methods(vec!["GET", "POST"])
             .allowed_headers(vec![http::header::AUTHORIZATION, http::header::ACCEPT])
+            .allowed_header(IDEMPOTENCY_KEY)
             .allowed_header(http::header::CONTENT_TYPE)
             .max_age(3600);
 
-        let keycloak_auth = KeycloakAuth {
-            detailed_responses: true,
-            passthrough_policy: AlwaysReturnPolicy,
-            keycloak_oid_public_key: DecodingKey::from_rsa_pem(KEYCLOAK_PK.as_bytes()).unwrap(),
-            required_roles: vec![],
+        // Routes submitting sudo calls also require the admin realm role
+        let admin_role = || Role::Realm {
+            role: env.keycloak_admin_role.clone(),
         };
 
         App::new()
@@ -77,41 +169,77 @@ async fn main() -> std::io::Result<()> {
             .wrap(middleware::Logger::default())
             .app_data(Data::new(state.clone()))
             .app_data(Data::new(env.clone()))
-            .wrap(keycloak_auth)
             .wrap(cors)
-            .route("user/verify_seed", web::get().to(user::verify_seed))
-            .route("account/create", web::post().to(account::create))
-            .route("account/fund", web::post().to(account::fund))
-            .route("account/balance", web::post().to(account::balance))
-            .route("account/transfer", web::post().to(account::transfer))
-            .route("asset/create_class", web::post().to(asset::create_class))
-            .route("asset/create", web::post().to(asset::create))
-            .route("asset/mint", web::post().to(asset::mint))
-            .route("asset/burn", web::post().to(asset::burn))
-            .route("asset/balance", web::post().to(asset::balance))
-            .route("asset/transfer_from", web::post().to(asset::transfer_from))
-            .route("currency/issue", web::post().to(currency::issue))
-            .route("currency/issuance", web::post().to(currency::issuance))
-            .route("currency/mint", web::post().to(currency::mint))
-            .route("currency/burn", web::post().to(currency::burn))
-            .route("currency/supply", web::post().to(currency::supply))
-            .route("dex/create", web::post().to(dex::create))
-            .route("dex/buy_assets", web::post().to(dex::buy_assets))
-            .route("dex/sell_assets", web::post().to(dex::sell_assets))
-            .route("dex/add_liquidity", web::post().to(dex::add_liquidity))
             .route(
-                "dex/remove_liquidity",
-                web::post().to(dex::remove_liquidity),
+                r"asset/metadata/{class_id}/{asset_id:\d+}.json",
+                web::get().to(asset::metadata_json),
+            )
+            .route("blob/{sha256}", web::get().to(blob::get))
+            .service(
+                web::scope("")
+                    .wrap(Idempotency::new(idempotency_store.clone(), idempotency_ttl))
+                    .wrap(keycloak_auth(vec![]))
+                    .route("user/verify_seed", web::get().to(user::verify_seed))
+                    .route("user/metadata", web::get().to(user::get_metadata))
+                    .route("user/metadata", web::post().to(user::update_metadata))
+                    .route("account/create", web::post().to(account::create))
+                    .route("account/fund", web::post().to(account::fund))
+                    .route("account/balance", web::post().to(account::balance))
+                    .route("account/transfer", web::post().to(account::transfer))
+                    .route("account/history", web::post().to(account::history))
+                    .route("asset/create_class", web::post().to(asset::create_class))
+                    .route("asset/create", web::post().to(asset::create))
+                    .route("asset/mint", web::post().to(asset::mint))
+                    .route("asset/burn", web::post().to(asset::burn))
+                    .route("asset/balance", web::post().to(asset::balance))
+                    .route("asset/balances", web::post().to(asset::balances))
+                    .route("asset/holders", web::post().to(asset::holders))
+                    .route("asset/holders/check", web::post().to(asset::holders_check))
+                    .route("asset/snapshot", web::post().to(asset::snapshot))
+                    .route("asset/class_info", web::post().to(asset::class_info))
+                    .route("asset/info", web::post().to(asset::info))
+                    .route("asset/classes", web::post().to(asset::classes))
+                    .route("asset/update_class_metadata", web::post().to(asset::update_class_metadata))
+                    .route("asset/update_metadata", web::post().to(asset::update_metadata))
+                    .route("asset/transfer_from", web::post().to(asset::transfer_from))
+                    .route("asset/batch_mint", web::post().to(asset::batch_mint))
+                    .route("asset/batch_transfer_from", web::post().to(asset::batch_transfer_from))
+                    .route("asset/is_approved_for_all", web::post().to(asset::is_approved_for_all))
+                    .service(
+                        web::resource("currency/issue")
+                            .wrap(keycloak_auth(vec![admin_role()]))
+                            .route(web::post().to(currency::issue)),
+                    )
+                    .route("currency/issuance", web::post().to(currency::issuance))
+                    .route("currency/mint", web::post().to(currency::mint))
+                    .route("currency/burn", web::post().to(currency::burn))
+                    .route("currency/supply", web::post().to(currency::supply))
+                    .route("currency/balance", web::post().to(currency::balance))
+                    .route("currency/transfer", web::post().to(currency::transfer))
+                    .route("dex/create", web::post().to(dex::create))
+                    .route("dex/buy_assets", web::post().to(dex::buy_assets))
+                    .route("dex/sell_assets", web::post().to(dex::sell_assets))
+                    .route("dex/add_liquidity", web::post().to(dex::add_liquidity))
+                    .route(
+                        "dex/remove_liquidity",
+                        web::post().to(dex::remove_liquidity),
+                    )
+                    .route("escrow/create", web::post().to(escrow::create_escrow))
+                    .route("escrow/refund", web::post().to(escrow::refund_assets))
+                    .route("escrow/deposit", web::post().to(escrow::deposit_assets))
+                    .route("bundle/register", web::post().to(bundle::register_bundle))
+                    .route("bundle/mint", web::post().to(bundle::mint_bundle))
+                    .route("bundle/burn", web::post().to(bundle::burn_bundle))
+                    .service(
+                        web::resource("sudo/call")
+                            .wrap(keycloak_auth(vec![admin_role()]))
+                            .route(web::post().to(sudo::call)),
+                    )
+                    .route("tx/status/{id}", web::get().to(tx::status)),
             )
-            .route("escrow/create", web::post().to(escrow::create_escrow))
-            .route("escrow/refund", web::post().to(escrow::refund_assets))
-            .route("escrow/deposit", web::post().to(escrow::deposit_assets))
-            .route("bundle/register", web::post().to(bundle::register_bundle))
-            .route("bundle/mint", web::post().to(bundle::mint_bundle))
-            .route("bundle/burn", web::post().to(bundle::burn_bundle))
     })
     .bind((opt.listen.host_str().unwrap(), opt.listen.port().unwrap()))?
-    .workers(1)
+    .workers(opt.workers)
     .run()
     .await
 }
\ No newline at end of file
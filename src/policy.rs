@@ -0,0 +1,151 @@
+use crate::state::*;
+use crate::util::*;
+use actix_web::{error, http::StatusCode, HttpResponse};
+use serde::Deserialize;
+use serde_json::json;
+use sp_core::crypto::AccountId32;
+
+/// Key of the class metadata holding the transfer policy of its assets
+pub const TRANSFER_POLICY_KEY: &str = "transfer_policy";
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferPolicy {
+    /// Any holder or operator can transfer
+    Transferable,
+    /// Assets never move between accounts once minted
+    Soulbound,
+    /// Only the class owner can transfer
+    IssuerOnly,
+}
+
+/// Transfer refused by the policy of a class, answered with `403 Forbidden`
+#[derive(Debug, PartialEq)]
+pub struct PolicyError {
+    pub message: String,
+}
+
+impl std::fmt::Display for PolicyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl error::ResponseError for PolicyError {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::FORBIDDEN
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::Forbidden().json(RequestError {
+            message: json!(self.message),
+        })
+    }
+}
+
+fn parse(metadata: &serde_json::Value) -> Result<TransferPolicy, String> {
+    match metadata.get(TRANSFER_POLICY_KEY) {
+        Some(policy) => serde_json::from_value(policy.clone()).map_err(|_| {
+            format!(
+                "Invalid {}, expected transferable, soulbound or issuer_only",
+                TRANSFER_POLICY_KEY
+            )
+        }),
+        None => Ok(TransferPolicy::Transferable),
+    }
+}
+
+/// Check that the transfer policy declared in class metadata, if any, is known
+pub fn check_class_metadata(metadata: &serde_json::Value) -> Result<(), HttpResponse> {
+    parse(metadata).map(|_| ()).map_err(|message| {
+        HttpResponse::UnprocessableEntity().json(RequestError {
+            message: json!(message),
+        })
+    })
+}
+
+/// Check that `who` may transfer assets of a class owned by `owner` under `policy`
+fn check_policy(
+    policy: TransferPolicy,
+    class_id: u64,
+    owner: &AccountId32,
+    who: &AccountId32,
+) -> Result<(), PolicyError> {
+    match policy {
+        TransferPolicy::Transferable => Ok(()),
+        TransferPolicy::Soulbound => Err(PolicyError {
+            message: format!("Assets of class {} are soulbound and cannot be transferred", class_id),
+        }),
+        TransferPolicy::IssuerOnly if owner != who => Err(PolicyError {
+            message: format!(
+                "Assets of class {} can only be transferred by the class owner",
+                class_id
+            ),
+        }),
+        TransferPolicy::IssuerOnly => Ok(()),
+    }
+}
+
+/// Check that `who` may transfer assets of `class_id`. Classes that do not
+/// exist are left for the chain to reject.
+pub async fn check_transfer(api: &RuntimeApi, class_id: u64, who: &AccountId32) -> error::Result<()> {
+    let class = api
+        .storage()
+        .asset()
+        .classes(class_id, None)
+        .await
+        .map_err(map_subxt_err)?;
+    let class = match class {
+        Some(class) => class,
+        None => return Ok(()),
+    };
+    let metadata = serde_json::from_slice(&class.metadata).unwrap_or(serde_json::Value::Null);
+    let policy = parse(&metadata).map_err(|message| PolicyError { message })?;
+    check_policy(policy, class_id, &class.owner, who)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::ResponseError;
+
+    #[test]
+    fn parse_policies() {
+        assert_eq!(parse(&json!({})), Ok(TransferPolicy::Transferable));
+        assert_eq!(parse(&serde_json::Value::Null), Ok(TransferPolicy::Transferable));
+        assert_eq!(
+            parse(&json!({ "transfer_policy": "soulbound" })),
+            Ok(TransferPolicy::Soulbound)
+        );
+        assert_eq!(
+            parse(&json!({ "transfer_policy": "issuer_only" })),
+            Ok(TransferPolicy::IssuerOnly)
+        );
+        assert!(parse(&json!({ "transfer_policy": "frozen" })).is_err());
+        assert!(check_class_metadata(&json!({ "transfer_policy": 1 })).is_err());
+        assert!(check_class_metadata(&json!({ "name": "class" })).is_ok());
+    }
+
+    #[test]
+    fn check_policies() {
+        let owner = AccountId32::new([1; 32]);
+        let other = AccountId32::new([2; 32]);
+        assert!(check_policy(TransferPolicy::Transferable, 7, &owner, &other).is_ok());
+        assert!(check_policy(TransferPolicy::Soulbound, 7, &owner, &owner).is_err());
+        assert!(check_policy(TransferPolicy::IssuerOnly, 7, &owner, &owner).is_ok());
+        let refused = check_policy(TransferPolicy::IssuerOnly, 7, &owner, &other).unwrap_err();
+        assert!(refused.message.contains("class 7"));
+    }
+
+    #[test]
+    fn policy_error_is_forbidden() {
+        let refused = PolicyError {
+            message: "refused".to_string(),
+        };
+        assert_eq!(refused.status_code(), StatusCode::FORBIDDEN);
+        assert_eq!(refused.error_response().status(), StatusCode::FORBIDDEN);
+        let refused: actix_web::Error = refused.into();
+        assert_eq!(refused.as_response_error().status_code(), StatusCode::FORBIDDEN);
+    }
+}
@@ -0,0 +1,620 @@
+use codec::{Compact, Decode, Encode};
+use frame_metadata::{RuntimeMetadata, RuntimeMetadataPrefixed, RuntimeMetadataV14};
+use once_cell::sync::Lazy;
+use scale_info::{form::PortableForm, Field, PortableRegistry, TypeDef, TypeDefPrimitive, Variant};
+use serde_json::{json, Value};
+use std::str::FromStr;
+
+/// Runtime metadata the `sugarfunge` module is generated from
+const METADATA: &[u8] = include_bytes!("../sugarfunge_metadata.scale");
+
+/// `METADATA` decoded on first use
+static RUNTIME_METADATA: Lazy<Result<RuntimeMetadataV14, String>> = Lazy::new(|| {
+    let metadata = RuntimeMetadataPrefixed::decode(&mut &METADATA[..]).map_err(|e| e.to_string())?;
+    match metadata.1 {
+        RuntimeMetadata::V14(metadata) => Ok(metadata),
+        _ => Err("Unsupported runtime metadata version".to_string()),
+    }
+});
+
+pub fn metadata() -> Result<&'static RuntimeMetadataV14, String> {
+    RUNTIME_METADATA.as_ref().map_err(|e| e.clone())
+}
+
+fn call_variant<'a>(
+    metadata: &'a RuntimeMetadataV14,
+    pallet: &str,
+    call: &str,
+) -> Result<(u8, &'a Variant<PortableForm>), String> {
+    let pallet_metadata = metadata
+        .pallets
+        .iter()
+        .find(|p| p.name == pallet)
+        .ok_or_else(|| format!("Unknown pallet {}", pallet))?;
+    let calls = pallet_metadata
+        .calls
+        .as_ref()
+        .ok_or_else(|| format!("Pallet {} has no calls", pallet))?;
+    let variant = match metadata.types.resolve(calls.ty.id()).map(|ty| ty.type_def()) {
+        Some(TypeDef::Variant(calls)) => calls.variants().iter().find(|v| v.name() == call),
+        _ => None,
+    }
+    .ok_or_else(|| format!("Unknown call {}.{}", pallet, call))?;
+    Ok((pallet_metadata.index, variant))
+}
+
+/// SCALE encode a runtime call given its pallet, call name and JSON arguments
+/// keyed by argument name
+pub fn encode_call(
+    metadata: &RuntimeMetadataV14,
+    pallet: &str,
+    call: &str,
+    args: &Value,
+) -> Result<Vec<u8>, String> {
+    let (pallet_index, variant) = call_variant(metadata, pallet, call)?;
+    let mut out = vec![pallet_index, variant.index()];
+    encode_fields(&metadata.types, variant.fields(), args, call, &mut out)?;
+    Ok(out)
+}
+
+/// Wrap an encoded runtime call in `Sudo::sudo`, or in `Sudo::sudo_as` when
+/// `as_account` is set
+pub fn encode_sudo(
+    metadata: &RuntimeMetadataV14,
+    call: &[u8],
+    as_account: Option<&sp_core::crypto::AccountId32>,
+) -> Result<Vec<u8>, String> {
+    let name = if as_account.is_some() { "sudo_as" } else { "sudo" };
+    let (pallet_index, variant) = call_variant(metadata, "Sudo", name)?;
+    let mut out = vec![pallet_index, variant.index()];
+    if let Some(account) = as_account {
+        subxt::sp_runtime::MultiAddress::<sp_core::crypto::AccountId32, ()>::Id(account.clone()).encode_to(&mut out);
+    }
+    out.extend(call);
+    Ok(out)
+}
+
+/// Decode the fields of a runtime event to a JSON object
+pub fn decode_event(
+    metadata: &RuntimeMetadataV14,
+    pallet: &str,
+    event: &str,
+    data: &[u8],
+) -> Result<Value, String> {
+    let event_ty = metadata
+        .pallets
+        .iter()
+        .find(|p| p.name == pallet)
+        .and_then(|p| p.event.as_ref())
+        .ok_or_else(|| format!("Unknown events of pallet {}", pallet))?;
+    let variant = match metadata.types.resolve(event_ty.ty.id()).map(|ty| ty.type_def()) {
+        Some(TypeDef::Variant(events)) => events.variants().iter().find(|v| v.name() == event),
+        _ => None,
+    }
+    .ok_or_else(|| format!("Unknown event {}.{}", pallet, event))?;
+    let mut input = data;
+    decode_fields(&metadata.types, variant.fields(), &mut input)
+}
+
+fn type_name(registry: &PortableRegistry, id: u32) -> String {
+    registry
+        .resolve(id)
+        .map(|ty| ty.path().segments().join("::"))
+        .unwrap_or_default()
+}
+
+fn is_account(registry: &PortableRegistry, id: u32) -> bool {
+    type_name(registry, id) == "sp_core::crypto::AccountId32"
+}
+
+fn is_option(registry: &PortableRegistry, id: u32) -> bool {
+    type_name(registry, id) == "Option"
+}
+
+fn encode_fields(
+    registry: &PortableRegistry,
+    fields: &[Field<PortableForm>],
+    value: &Value,
+    at: &str,
+    out: &mut Vec<u8>,
+) -> Result<(), String> {
+    if fields.is_empty() {
+        return match value {
+            Value::Null => Ok(()),
+            Value::Object(object) if object.is_empty() => Ok(()),
+            Value::Array(items) if items.is_empty() => Ok(()),
+            _ => Err(format!("{}: expected no arguments", at)),
+        };
+    }
+    if fields.iter().all(|field| field.name().is_some()) {
+        let object = value
+            .as_object()
+            .ok_or_else(|| format!("{}: expected an object", at))?;
+        if let Some(unknown) = object
+            .keys()
+            .find(|key| !fields.iter().any(|field| field.name().map(|name| name.as_str()) == Some(key.as_str())))
+        {
+            return Err(format!("{}: unknown argument {}", at, unknown));
+        }
+        for field in fields {
+            let name = field.name().unwrap();
+            let field_value = object
+                .get(name.as_str())
+                .ok_or_else(|| format!("{}: missing argument {}", at, name))?;
+            encode_value(registry, field.ty().id(), field_value, &format!("{}.{}", at, name), out)?;
+        }
+        return Ok(());
+    }
+    if let [field] = fields {
+        return encode_value(registry, field.ty().id(), value, at, out);
+    }
+    let items = value
+        .as_array()
+        .filter(|items| items.len() == fields.len())
+        .ok_or_else(|| format!("{}: expected an array of {} items", at, fields.len()))?;
+    for (index, (field, item)) in fields.iter().zip(items).enumerate() {
+        encode_value(registry, field.ty().id(), item, &format!("{}[{}]", at, index), out)?;
+    }
+    Ok(())
+}
+
+fn encode_bytes(value: &Value, at: &str) -> Result<Vec<u8>, String> {
+    match value {
+        Value::String(text) if text.starts_with("0x") => {
+            hex::decode(&text[2..]).map_err(|_| format!("{}: invalid hex", at))
+        }
+        Value::String(text) => Ok(text.as_bytes().to_vec()),
+        Value::Array(items) => items
+            .iter()
+            .map(|item| {
+                item.as_u64()
+                    .filter(|byte| *byte <= u8::MAX as u64)
+                    .map(|byte| byte as u8)
+                    .ok_or_else(|| format!("{}: expected bytes", at))
+            })
+            .collect(),
+        _ => Err(format!("{}: expected a hex string", at)),
+    }
+}
+
+fn parse_unsigned(value: &Value, at: &str) -> Result<u128, String> {
+    match value {
+        Value::Number(number) => number.as_u64().map(u128::from),
+        Value::String(text) => text.parse().ok(),
+        _ => None,
+    }
+    .ok_or_else(|| format!("{}: expected an unsigned integer", at))
+}
+
+fn parse_signed(value: &Value, at: &str) -> Result<i128, String> {
+    match value {
+        Value::Number(number) => number.as_i64().map(i128::from),
+        Value::String(text) => text.parse().ok(),
+        _ => None,
+    }
+    .ok_or_else(|| format!("{}: expected an integer", at))
+}
+
+fn encode_primitive(
+    primitive: &TypeDefPrimitive,
+    value: &Value,
+    at: &str,
+    out: &mut Vec<u8>,
+) -> Result<(), String> {
+    let range = |at: &str| format!("{}: out of range", at);
+    match primitive {
+        TypeDefPrimitive::Bool => value
+            .as_bool()
+            .ok_or_else(|| format!("{}: expected a boolean", at))?
+            .encode_to(out),
+        TypeDefPrimitive::Str => value
+            .as_str()
+            .ok_or_else(|| format!("{}: expected a string", at))?
+            .encode_to(out),
+        TypeDefPrimitive::U8 => u8::try_from(parse_unsigned(value, at)?).map_err(|_| range(at))?.encode_to(out),
+        TypeDefPrimitive::U16 => u16::try_from(parse_unsigned(value, at)?).map_err(|_| range(at))?.encode_to(out),
+        TypeDefPrimitive::U32 => u32::try_from(parse_unsigned(value, at)?).map_err(|_| range(at))?.encode_to(out),
+        TypeDefPrimitive::U64 => u64::try_from(parse_unsigned(value, at)?).map_err(|_| range(at))?.encode_to(out),
+        TypeDefPrimitive::U128 => parse_unsigned(value, at)?.encode_to(out),
+        TypeDefPrimitive::I8 => i8::try_from(parse_signed(value, at)?).map_err(|_| range(at))?.encode_to(out),
+        TypeDefPrimitive::I16 => i16::try_from(parse_signed(value, at)?).map_err(|_| range(at))?.encode_to(out),
+        TypeDefPrimitive::I32 => i32::try_from(parse_signed(value, at)?).map_err(|_| range(at))?.encode_to(out),
+        TypeDefPrimitive::I64 => i64::try_from(parse_signed(value, at)?).map_err(|_| range(at))?.encode_to(out),
+        TypeDefPrimitive::I128 => parse_signed(value, at)?.encode_to(out),
+        _ => return Err(format!("{}: unsupported type {:?}", at, primitive)),
+    }
+    Ok(())
+}
+
+/// SCALE encode a JSON value as the type `id` of the registry. Accounts are
+/// SS58 or hex strings, byte vectors and arrays are hex strings, enums are
+/// the variant name or `{ "Variant": fields }` and `Option` is `null` or the value.
+pub fn encode_value(
+    registry: &PortableRegistry,
+    id: u32,
+    value: &Value,
+    at: &str,
+    out: &mut Vec<u8>,
+) -> Result<(), String> {
+    if is_account(registry, id) {
+        let account = value
+            .as_str()
+            .and_then(|account| {
+                sp_core::crypto::AccountId32::from_str(account.trim_start_matches("0x")).ok()
+            })
+            .ok_or_else(|| format!("{}: invalid account", at))?;
+        account.encode_to(out);
+        return Ok(());
+    }
+    let ty = registry
+        .resolve(id)
+        .ok_or_else(|| format!("{}: unknown type {}", at, id))?;
+    match ty.type_def() {
+        TypeDef::Composite(composite) => encode_fields(registry, composite.fields(), value, at, out),
+        TypeDef::Variant(variant) => {
+            if is_option(registry, id) {
+                return match value {
+                    Value::Null => {
+                        out.push(0);
+                        Ok(())
+                    }
+                    value => {
+                        let some = variant
+                            .variants()
+                            .iter()
+                            .find(|v| v.name() == "Some")
+                            .ok_or_else(|| format!("{}: invalid Option type", at))?;
+                        out.push(some.index());
+                        encode_fields(registry, some.fields(), value, at, out)
+                    }
+                };
+            }
+            let (name, fields) = match value {
+                Value::String(name) => (name.as_str(), &Value::Null),
+                Value::Object(object) if object.len() == 1 => {
+                    let (name, fields) = object.iter().next().unwrap();
+                    (name.as_str(), fields)
+                }
+                _ => return Err(format!("{}: expected a variant name or {{ \"Variant\": ... }}", at)),
+            };
+            let variant = variant
+                .variants()
+                .iter()
+                .find(|v| v.name() == name)
+                .ok_or_else(|| format!("{}: unknown variant {}", at, name))?;
+            out.push(variant.index());
+            encode_fields(registry, variant.fields(), fields, &format!("{}.{}", at, name), out)
+        }
+        TypeDef::Sequence(sequence) => {
+            let item = sequence.type_param().id();
+            if let Some(TypeDef::Primitive(TypeDefPrimitive::U8)) = registry.resolve(item).map(|ty| ty.type_def()) {
+                encode_bytes(value, at)?.encode_to(out);
+                return Ok(());
+            }
+            let items = value
+                .as_array()
+                .ok_or_else(|| format!("{}: expected an array", at))?;
+            Compact(items.len() as u32).encode_to(out);
+            for (index, item_value) in items.iter().enumerate() {
+                encode_value(registry, item, item_value, &format!("{}[{}]", at, index), out)?;
+            }
+            Ok(())
+        }
+        TypeDef::Array(array) => {
+            let item = array.type_param().id();
+            if let Some(TypeDef::Primitive(TypeDefPrimitive::U8)) = registry.resolve(item).map(|ty| ty.type_def()) {
+                let bytes = encode_bytes(value, at)?;
+                if bytes.len() != array.len() as usize {
+                    return Err(format!("{}: expected {} bytes", at, array.len()));
+                }
+                out.extend(bytes);
+                return Ok(());
+            }
+            let items = value
+                .as_array()
+                .filter(|items| items.len() == array.len() as usize)
+                .ok_or_else(|| format!("{}: expected an array of {} items", at, array.len()))?;
+            for (index, item_value) in items.iter().enumerate() {
+                encode_value(registry, item, item_value, &format!("{}[{}]", at, index), out)?;
+            }
+            Ok(())
+        }
+        TypeDef::Tuple(tuple) => {
+            let fields = tuple.fields();
+            if fields.is_empty() {
+                return Ok(());
+            }
+            let items = value
+                .as_array()
+                .filter(|items| items.len() == fields.len())
+                .ok_or_else(|| format!("{}: expected an array of {} items", at, fields.len()))?;
+            for (index, (field, item)) in fields.iter().zip(items).enumerate() {
+                encode_value(registry, field.id(), item, &format!("{}[{}]", at, index), out)?;
+            }
+            Ok(())
+        }
+        TypeDef::Primitive(primitive) => encode_primitive(primitive, value, at, out),
+        TypeDef::Compact(compact) => {
+            let number = parse_unsigned(value, at)?;
+            let max = match registry.resolve(compact.type_param().id()).map(|ty| ty.type_def()) {
+                Some(TypeDef::Primitive(TypeDefPrimitive::U8)) => u8::MAX as u128,
+                Some(TypeDef::Primitive(TypeDefPrimitive::U16)) => u16::MAX as u128,
+                Some(TypeDef::Primitive(TypeDefPrimitive::U32)) => u32::MAX as u128,
+                Some(TypeDef::Primitive(TypeDefPrimitive::U64)) => u64::MAX as u128,
+                _ => u128::MAX,
+            };
+            if number > max {
+                return Err(format!("{}: out of range", at));
+            }
+            // The compact encoding of a number does not depend on its type
+            Compact(number).encode_to(out);
+            Ok(())
+        }
+        TypeDef::BitSequence(_) => Err(format!("{}: bit sequences are not supported", at)),
+    }
+}
+
+fn decode_fields(
+    registry: &PortableRegistry,
+    fields: &[Field<PortableForm>],
+    input: &mut &[u8],
+) -> Result<Value, String> {
+    if fields.iter().all(|field| field.name().is_some()) {
+        let mut object = serde_json::Map::new();
+        for field in fields {
+            let value = decode_value(registry, field.ty().id(), input)?;
+            object.insert(field.name().unwrap().to_string(), value);
+        }
+        return Ok(Value::Object(object));
+    }
+    if let [field] = fields {
+        return decode_value(registry, field.ty().id(), input);
+    }
+    fields
+        .iter()
+        .map(|field| decode_value(registry, field.ty().id(), input))
+        .collect::<Result<Vec<_>, _>>()
+        .map(Value::Array)
+}
+
+fn take_bytes(input: &mut &[u8], len: usize) -> Result<Value, String> {
+    let data: &[u8] = input;
+    let bytes = data.get(..len).ok_or_else(|| "Not enough data".to_string())?;
+    *input = &data[len..];
+    Ok(json!(format!("0x{}", hex::encode(bytes))))
+}
+
+/// JSON numbers above u64 are returned as strings
+fn number<T: Into<u128>>(value: T) -> Value {
+    let value = value.into();
+    match u64::try_from(value) {
+        Ok(value) => json!(value),
+        Err(_) => json!(value.to_string()),
+    }
+}
+
+fn signed<T: Into<i128>>(value: T) -> Value {
+    let value = value.into();
+    match i64::try_from(value) {
+        Ok(value) => json!(value),
+        Err(_) => json!(value.to_string()),
+    }
+}
+
+/// Decode the SCALE encoded type `id` of the registry to JSON, in the shapes
+/// accepted by `encode_value`
+pub fn decode_value(registry: &PortableRegistry, id: u32, input: &mut &[u8]) -> Result<Value, String> {
+    let err = |e: codec::Error| e.to_string();
+    if is_account(registry, id) {
+        let account = sp_core::crypto::AccountId32::decode(input).map_err(err)?;
+        return Ok(json!(account.to_string()));
+    }
+    let ty = registry
+        .resolve(id)
+        .ok_or_else(|| format!("Unknown type {}", id))?;
+    match ty.type_def() {
+        TypeDef::Composite(composite) => decode_fields(registry, composite.fields(), input),
+        TypeDef::Variant(variant) => {
+            let index = u8::decode(input).map_err(err)?;
+            let variant = variant
+                .variants()
+                .iter()
+                .find(|v| v.index() == index)
+                .ok_or_else(|| format!("Unknown variant {} of {}", index, type_name(registry, id)))?;
+            if is_option(registry, id) {
+                return match variant.fields() {
+                    [] => Ok(Value::Null),
+                    fields => decode_fields(registry, fields, input),
+                };
+            }
+            if variant.fields().is_empty() {
+                return Ok(json!(variant.name()));
+            }
+            let mut object = serde_json::Map::new();
+            object.insert(variant.name().to_string(), decode_fields(registry, variant.fields(), input)?);
+            Ok(Value::Object(object))
+        }
+        TypeDef::Sequence(sequence) => {
+            let item = sequence.type_param().id();
+            let Compact(len) = Compact::<u32>::decode(input).map_err(err)?;
+            if let Some(TypeDef::Primitive(TypeDefPrimitive::U8)) = registry.resolve(item).map(|ty| ty.type_def()) {
+                return take_bytes(input, len as usize);
+            }
+            (0..len)
+                .map(|_| decode_value(registry, item, input))
+                .collect::<Result<Vec<_>, _>>()
+                .map(Value::Array)
+        }
+        TypeDef::Array(array) => {
+            let item = array.type_param().id();
+            let len = array.len() as usize;
+            if let Some(TypeDef::Primitive(TypeDefPrimitive::U8)) = registry.resolve(item).map(|ty| ty.type_def()) {
+                return take_bytes(input, len);
+            }
+            (0..len)
+                .map(|_| decode_value(registry, item, input))
+                .collect::<Result<Vec<_>, _>>()
+                .map(Value::Array)
+        }
+        TypeDef::Tuple(tuple) => {
+            if tuple.fields().is_empty() {
+                return Ok(Value::Null);
+            }
+            tuple
+                .fields()
+                .iter()
+                .map(|field| decode_value(registry, field.id(), input))
+                .collect::<Result<Vec<_>, _>>()
+                .map(Value::Array)
+        }
+        TypeDef::Primitive(primitive) => match primitive {
+            TypeDefPrimitive::Bool => Ok(json!(bool::decode(input).map_err(err)?)),
+            TypeDefPrimitive::Str => Ok(json!(String::decode(input).map_err(err)?)),
+            TypeDefPrimitive::U8 => Ok(number(u8::decode(input).map_err(err)?)),
+            TypeDefPrimitive::U16 => Ok(number(u16::decode(input).map_err(err)?)),
+            TypeDefPrimitive::U32 => Ok(number(u32::decode(input).map_err(err)?)),
+            TypeDefPrimitive::U64 => Ok(number(u64::decode(input).map_err(err)?)),
+            TypeDefPrimitive::U128 => Ok(number(u128::decode(input).map_err(err)?)),
+            TypeDefPrimitive::I8 => Ok(signed(i8::decode(input).map_err(err)?)),
+            TypeDefPrimitive::I16 => Ok(signed(i16::decode(input).map_err(err)?)),
+            TypeDefPrimitive::I32 => Ok(signed(i32::decode(input).map_err(err)?)),
+            TypeDefPrimitive::I64 => Ok(signed(i64::decode(input).map_err(err)?)),
+            TypeDefPrimitive::I128 => Ok(signed(i128::decode(input).map_err(err)?)),
+            _ => Err(format!("Unsupported type {:?}", primitive)),
+        },
+        TypeDef::Compact(_) => Ok(number(Compact::<u128>::decode(input).map_err(err)?.0)),
+        TypeDef::BitSequence(_) => Err("Bit sequences are not supported".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sugarfunge::runtime_types::{
+        frame_system, pallet_balances, pallet_scheduler, pallet_sudo, sugarfunge_asset,
+        sugarfunge_runtime::Call,
+    };
+    use sp_core::crypto::AccountId32;
+    use subxt::sp_runtime::MultiAddress;
+
+    fn encode(pallet: &str, call: &str, args: Value) -> Result<Vec<u8>, String> {
+        encode_call(metadata().unwrap(), pallet, call, &args)
+    }
+
+    fn remark() -> Call {
+        Call::System(frame_system::pallet::Call::remark { remark: vec![1, 2] })
+    }
+
+    #[test]
+    fn encode_asset_create_class() {
+        let owner = AccountId32::new([1; 32]);
+        let encoded = encode(
+            "Asset",
+            "create_class",
+            json!({ "owner": owner.to_string(), "class_id": 7, "metadata": "0x7b7d" }),
+        )
+        .unwrap();
+        let call = Call::Asset(sugarfunge_asset::pallet::Call::create_class {
+            owner,
+            class_id: 7,
+            metadata: b"{}".to_vec(),
+        });
+        assert_eq!(encoded, call.encode());
+        assert_eq!(Call::decode(&mut &encoded[..]).unwrap().encode(), encoded);
+    }
+
+    #[test]
+    fn encode_bytes_as_hex() {
+        let encoded = encode("System", "remark", json!({ "remark": "0x0102" })).unwrap();
+        assert_eq!(encoded, remark().encode());
+    }
+
+    #[test]
+    fn encode_compact() {
+        let dest = AccountId32::new([2; 32]);
+        let encoded = encode(
+            "Balances",
+            "transfer",
+            json!({ "dest": { "Id": dest.to_string() }, "value": "1000000000000000000000" }),
+        )
+        .unwrap();
+        let call = Call::Balances(pallet_balances::pallet::Call::transfer {
+            dest: MultiAddress::Id(dest),
+            value: 1_000_000_000_000_000_000_000,
+        });
+        assert_eq!(encoded, call.encode());
+    }
+
+    #[test]
+    fn encode_option() {
+        let system_remark = json!({ "System": { "remark": { "remark": "0x0102" } } });
+        let encoded = encode(
+            "Scheduler",
+            "schedule",
+            json!({ "when": 10, "maybe_periodic": [5, 2], "priority": 1, "call": system_remark }),
+        )
+        .unwrap();
+        let call = Call::Scheduler(pallet_scheduler::pallet::Call::schedule {
+            when: 10,
+            maybe_periodic: Some((5, 2)),
+            priority: 1,
+            call: Box::new(remark()),
+        });
+        assert_eq!(encoded, call.encode());
+
+        let encoded = encode(
+            "Scheduler",
+            "schedule",
+            json!({ "when": 10, "maybe_periodic": null, "priority": 1, "call": system_remark }),
+        )
+        .unwrap();
+        let call = Call::Scheduler(pallet_scheduler::pallet::Call::schedule {
+            when: 10,
+            maybe_periodic: None,
+            priority: 1,
+            call: Box::new(remark()),
+        });
+        assert_eq!(encoded, call.encode());
+    }
+
+    #[test]
+    fn reject_unknown_and_missing_arguments() {
+        let err = encode("System", "remark", json!({ "remark": "0x01", "extra": 1 })).unwrap_err();
+        assert!(err.contains("unknown argument extra"), "{}", err);
+        let err = encode("System", "remark", json!({})).unwrap_err();
+        assert!(err.contains("missing argument remark"), "{}", err);
+        let err = encode("Sudo", "sudo", json!({ "call": { "System": { "remark": {} } } })).unwrap_err();
+        assert!(err.contains("missing argument remark"), "{}", err);
+        let err = encode("Scheduler", "schedule", json!({ "when": 10, "priority": 1, "call": "0x" })).unwrap_err();
+        assert!(err.contains("missing argument maybe_periodic"), "{}", err);
+    }
+
+    #[test]
+    fn encode_compact_bounds() {
+        let dest = AccountId32::new([2; 32]);
+        let value = (u128::MAX).to_string();
+        assert!(encode("Balances", "transfer", json!({ "dest": { "Id": dest.to_string() }, "value": value })).is_ok());
+        let err = encode("Balances", "transfer", json!({ "dest": { "Id": dest.to_string() }, "value": "-1" }));
+        assert!(err.is_err());
+    }
+
+    #[test]
+    fn encode_sudo_wraps_encoded_call() {
+        let metadata = metadata().unwrap();
+        let encoded = remark().encode();
+
+        let sudo = encode_sudo(metadata, &encoded, None).unwrap();
+        let call = Call::Sudo(pallet_sudo::pallet::Call::sudo {
+            call: Box::new(remark()),
+        });
+        assert_eq!(sudo, call.encode());
+
+        let who = AccountId32::new([3; 32]);
+        let sudo_as = encode_sudo(metadata, &encoded, Some(&who)).unwrap();
+        let call = Call::Sudo(pallet_sudo::pallet::Call::sudo_as {
+            who: MultiAddress::Id(who),
+            call: Box::new(remark()),
+        });
+        assert_eq!(sudo_as, call.encode());
+    }
+}
@@ -0,0 +1,71 @@
+use actix_web::HttpResponse;
+use jsonschema::JSONSchema;
+use serde::{Deserialize, Serialize};
+
+/// Key of the class metadata holding the JSON Schema of the metadata of its assets
+pub const ASSET_SCHEMA_KEY: &str = "asset_schema";
+
+#[derive(Serialize, Deserialize)]
+pub struct SchemaErrorOutput {
+    path: String,
+    schema_path: String,
+    message: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SchemaValidationOutput {
+    message: String,
+    errors: Vec<SchemaErrorOutput>,
+}
+
+fn validation_err(message: &str, errors: Vec<SchemaErrorOutput>) -> HttpResponse {
+    HttpResponse::UnprocessableEntity().json(SchemaValidationOutput {
+        message: message.to_string(),
+        errors,
+    })
+}
+
+fn compile(schema: &serde_json::Value) -> Result<JSONSchema, HttpResponse> {
+    JSONSchema::compile(schema).map_err(|e| {
+        validation_err(
+            "Invalid asset schema in class metadata",
+            vec![SchemaErrorOutput {
+                path: format!("/{}{}", ASSET_SCHEMA_KEY, e.instance_path),
+                schema_path: e.schema_path.to_string(),
+                message: e.to_string(),
+            }],
+        )
+    })
+}
+
+/// Check that the asset schema declared in class metadata, if any, is a valid JSON Schema
+pub fn check_class_metadata(metadata: &serde_json::Value) -> Result<(), HttpResponse> {
+    match metadata.get(ASSET_SCHEMA_KEY) {
+        Some(schema) => compile(schema).map(|_| ()),
+        None => Ok(()),
+    }
+}
+
+/// Validate asset metadata against the schema of its class, classes without a
+/// schema accept any metadata
+pub fn validate_asset_metadata(
+    class_metadata: &serde_json::Value,
+    metadata: &serde_json::Value,
+) -> Result<(), HttpResponse> {
+    let schema = match class_metadata.get(ASSET_SCHEMA_KEY) {
+        Some(schema) => compile(schema)?,
+        None => return Ok(()),
+    };
+    let result = schema.validate(metadata);
+    if let Err(errors) = result {
+        let errors = errors
+            .map(|e| SchemaErrorOutput {
+                path: e.instance_path.to_string(),
+                schema_path: e.schema_path.to_string(),
+                message: e.to_string(),
+            })
+            .collect();
+        return Err(validation_err("Metadata does not match the class asset schema", errors));
+    }
+    Ok(())
+}
@@ -0,0 +1,399 @@
+use crate::account;
+use crate::state::*;
+use crate::user::{self, TokenManager};
+use crate::util::*;
+use actix_web::error;
+use async_trait::async_trait;
+use crate::vault;
+use chacha20poly1305::XChaCha20Poly1305;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sp_core::Pair;
+use sp_keyring::AccountKeyring;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use subxt::sp_runtime::traits::IdentifyAccount;
+use subxt::PairSigner;
+use tokio::sync::Mutex as AsyncMutex;
+
+/// Source of the sr25519 keys used to sign extrinsics on behalf of a Keycloak user
+#[async_trait(?Send)]
+pub trait SignerBackend: Send + Sync {
+    /// Key pair for the given Keycloak `sub`, if the user has an account
+    async fn pair(&self, sub: &str) -> error::Result<Option<sp_core::sr25519::Pair>>;
+
+    /// Create and store a new account for the given Keycloak `sub`, returns the account
+    async fn create(&self, sub: &str) -> error::Result<String>;
+
+    /// Move the account of `from` to `to`, leaving `from` without an account.
+    /// Fails with a conflict if `to` already has an account.
+    async fn transfer(&self, from: &str, to: &str) -> error::Result<()>;
+}
+
+pub fn map_signer_err(message: &str) -> actix_web::Error {
+    let req_error = RequestError {
+        message: json!(message),
+    };
+    let req_error = serde_json::to_string_pretty(&req_error).unwrap();
+    error::ErrorBadRequest(req_error)
+}
+
+fn account_exists_err(sub: &str) -> actix_web::Error {
+    let req_error = RequestError {
+        message: json!(format!("User {} already has an account", sub)),
+    };
+    let req_error = serde_json::to_string_pretty(&req_error).unwrap();
+    error::ErrorConflict(req_error)
+}
+
+/// Get the transaction signer for a key pair, with the next free nonce of its account.
+///
+/// The nonce is reserved, so get the signer once the request is validated and
+/// submit right after.
+pub async fn tx_signer(data: &AppState, pair: sp_core::sr25519::Pair) -> error::Result<TxSigner> {
+    let mut signer = PairSigner::new(pair);
+    data.nonces.assign(data.api.get(), &mut signer).await?;
+    Ok(signer)
+}
+
+/// Get the key pair of a Keycloak user
+pub async fn user_pair(data: &AppState, sub: &str) -> error::Result<sp_core::sr25519::Pair> {
+    match data.signer.pair(sub).await? {
+        Some(pair) => Ok(pair),
+        None => Err(map_signer_err("Not found user Attributes")),
+    }
+}
+
+/// Get the transaction signer for a Keycloak user
+pub async fn pair_signer(data: &AppState, sub: &str) -> error::Result<TxSigner> {
+    let pair = user_pair(data, sub).await?;
+    tx_signer(data, pair).await
+}
+
+/// Seeds stored as the `user-seed` attribute of the Keycloak user
+pub struct KeycloakSigner {
+    tokens: Arc<TokenManager>,
+}
+
+impl KeycloakSigner {
+    pub fn new(tokens: Arc<TokenManager>) -> Self {
+        Self { tokens }
+    }
+}
+
+#[async_trait(?Send)]
+impl SignerBackend for KeycloakSigner {
+    async fn pair(&self, sub: &str) -> error::Result<Option<sp_core::sr25519::Pair>> {
+        match user::get_seed(&sub.to_string(), &self.tokens).await {
+            Ok(response) => match response.seed.clone().unwrap_or_default() {
+                seed if seed.is_empty() => Ok(None),
+                seed => Ok(Some(get_pair_from_seed(&seed)?)),
+            },
+            Err(_) => Err(map_signer_err("Failed to find user::getAttributes")),
+        }
+    }
+
+    async fn create(&self, sub: &str) -> error::Result<String> {
+        let seed = account::generate_seed();
+        let account = get_pair_from_seed(&seed)?.public().into_account();
+        match user::insert_seed_user(&sub.to_string(), &seed, &self.tokens).await {
+            Ok(_) => Ok(account.to_string()),
+            Err(_) => Err(map_signer_err("Error when insert attribute to user")),
+        }
+    }
+
+    async fn transfer(&self, from: &str, to: &str) -> error::Result<()> {
+        if self.pair(to).await?.is_some() {
+            return Err(account_exists_err(to));
+        }
+        match user::get_seed(&from.to_string(), &self.tokens).await {
+            Ok(response) => match response.seed.clone().unwrap_or_default() {
+                seed if seed.is_empty() => Err(map_signer_err("Not found user Attributes")),
+                seed => user::move_seed_user(&from.to_string(), &to.to_string(), &seed, &self.tokens)
+                    .await
+                    .map_err(|_| map_signer_err("Failed to find user::getAttributes")),
+            },
+            Err(_) => Err(map_signer_err("Failed to find user::getAttributes")),
+        }
+    }
+}
+
+/// Current keystore file format: a header with the key derivation parameters
+/// and the seeds sealed with the `sub` they belong to as associated data.
+/// Version 1 files are a bare `{sub: sealed}` map with an unsalted key.
+const KEYSTORE_VERSION: u32 = 2;
+
+#[derive(Serialize, Deserialize)]
+struct KeystoreFile {
+    version: u32,
+    kdf: vault::KeystoreKdf,
+    seeds: HashMap<String, String>,
+}
+
+fn keystore_err<E: std::fmt::Display>(e: E) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string())
+}
+
+/// Seeds stored in a local file, encrypted with XChaCha20-Poly1305
+pub struct KeystoreSigner {
+    path: PathBuf,
+    kdf: vault::KeystoreKdf,
+    cipher: XChaCha20Poly1305,
+    /// Held while the keystore file is written, so writes are not reordered
+    seeds: AsyncMutex<HashMap<String, String>>,
+}
+
+fn write_keystore(path: &std::path::Path, bytes: &[u8]) -> std::io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, bytes)?;
+    std::fs::rename(&tmp, path)
+}
+
+impl KeystoreSigner {
+    /// Open the keystore at `path`, the key is derived from `password`.
+    /// Version 1 keystores are rewritten in the current format.
+    pub fn open(path: PathBuf, password: &str) -> std::io::Result<Self> {
+        let file: Option<serde_json::Value> = match std::fs::read(&path) {
+            Ok(bytes) => Some(serde_json::from_slice(&bytes).map_err(keystore_err)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+        let decrypt_err = |sub: &str| keystore_err(format!("Failed to decrypt keystore entry for {}", sub));
+
+        let mut seeds = HashMap::new();
+        let (kdf, upgrade) = match file {
+            Some(file) if file.get("version").is_some() => {
+                let file: KeystoreFile = serde_json::from_value(file).map_err(keystore_err)?;
+                if file.version != KEYSTORE_VERSION {
+                    return Err(keystore_err(format!("Unsupported keystore version {}", file.version)));
+                }
+                let cipher = file.kdf.cipher(password).map_err(keystore_err)?;
+                for (sub, sealed) in file.seeds {
+                    let seed = vault::open(&cipher, &sealed, sub.as_bytes()).ok_or_else(|| decrypt_err(&sub))?;
+                    seeds.insert(sub, seed);
+                }
+                (file.kdf, false)
+            }
+            Some(file) => {
+                let entries: HashMap<String, String> = serde_json::from_value(file).map_err(keystore_err)?;
+                let cipher = vault::legacy_cipher_from_password(password);
+                for (sub, sealed) in entries {
+                    let seed = vault::open(&cipher, &sealed, &[]).ok_or_else(|| decrypt_err(&sub))?;
+                    seeds.insert(sub, seed);
+                }
+                (vault::KeystoreKdf::generate(), true)
+            }
+            None => (vault::KeystoreKdf::generate(), false),
+        };
+        let cipher = kdf.cipher(password).map_err(keystore_err)?;
+        let mut keystore = Self {
+            path,
+            kdf,
+            cipher,
+            seeds: AsyncMutex::new(seeds),
+        };
+        if upgrade {
+            let bytes = keystore.encode(keystore.seeds.get_mut());
+            write_keystore(&keystore.path, &bytes)?;
+            log::info!("Upgraded keystore to version {}", KEYSTORE_VERSION);
+        }
+        Ok(keystore)
+    }
+
+    fn encode(&self, seeds: &HashMap<String, String>) -> Vec<u8> {
+        let file = KeystoreFile {
+            version: KEYSTORE_VERSION,
+            kdf: self.kdf.clone(),
+            seeds: seeds
+                .iter()
+                .map(|(sub, seed)| (sub.clone(), vault::seal(&self.cipher, seed, sub.as_bytes())))
+                .collect(),
+        };
+        serde_json::to_vec_pretty(&file).unwrap_or_default()
+    }
+
+    /// Write the keystore on the blocking thread pool, call with the seeds locked
+    async fn save(&self, seeds: &HashMap<String, String>) -> error::Result<()> {
+        let path = self.path.clone();
+        let bytes = self.encode(seeds);
+        match actix_web::web::block(move || write_keystore(&path, &bytes)).await {
+            Ok(Ok(())) => Ok(()),
+            _ => Err(map_signer_err("Failed to write keystore")),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl SignerBackend for KeystoreSigner {
+    async fn pair(&self, sub: &str) -> error::Result<Option<sp_core::sr25519::Pair>> {
+        let seed = self.seeds.lock().await.get(sub).cloned();
+        match seed {
+            Some(seed) => Ok(Some(get_pair_from_seed(&seed)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn create(&self, sub: &str) -> error::Result<String> {
+        let seed = account::generate_seed();
+        let account = get_pair_from_seed(&seed)?.public().into_account();
+        let mut seeds = self.seeds.lock().await;
+        let previous = seeds.insert(sub.to_string(), seed);
+        if let Err(e) = self.save(&seeds).await {
+            match previous {
+                Some(previous) => seeds.insert(sub.to_string(), previous),
+                None => seeds.remove(sub),
+            };
+            return Err(e);
+        }
+        Ok(account.to_string())
+    }
+
+    async fn transfer(&self, from: &str, to: &str) -> error::Result<()> {
+        let mut seeds = self.seeds.lock().await;
+        if seeds.contains_key(to) {
+            return Err(account_exists_err(to));
+        }
+        let seed = seeds.remove(from).ok_or_else(|| map_signer_err("Not found user Attributes"))?;
+        seeds.insert(to.to_string(), seed.clone());
+        if let Err(e) = self.save(&seeds).await {
+            seeds.remove(to);
+            seeds.insert(from.to_string(), seed);
+            return Err(e);
+        }
+        Ok(())
+    }
+}
+
+/// Well-known development accounts (Alice, Bob, ...) assigned to users in memory
+#[derive(Default)]
+pub struct DevSigner {
+    accounts: Mutex<HashMap<String, AccountKeyring>>,
+}
+
+#[async_trait(?Send)]
+impl SignerBackend for DevSigner {
+    async fn pair(&self, sub: &str) -> error::Result<Option<sp_core::sr25519::Pair>> {
+        Ok(self.accounts.lock().unwrap().get(sub).map(|key| key.pair()))
+    }
+
+    async fn create(&self, sub: &str) -> error::Result<String> {
+        let mut accounts = self.accounts.lock().unwrap();
+        let key = AccountKeyring::iter()
+            .find(|key| !accounts.values().any(|used| used == key))
+            .ok_or_else(|| map_signer_err("No development accounts left"))?;
+        accounts.insert(sub.to_string(), key);
+        Ok(key.to_account_id().to_string())
+    }
+
+    async fn transfer(&self, from: &str, to: &str) -> error::Result<()> {
+        let mut accounts = self.accounts.lock().unwrap();
+        if accounts.contains_key(to) {
+            return Err(account_exists_err(to));
+        }
+        match accounts.remove(from) {
+            Some(key) => {
+                accounts.insert(to.to_string(), key);
+                Ok(())
+            }
+            None => Err(map_signer_err("Not found user Attributes")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use rand::Rng;
+
+    fn keystore_path() -> PathBuf {
+        let name = format!("keystore-{}.json", hex::encode(rand::thread_rng().gen::<[u8; 8]>()));
+        std::env::temp_dir().join(name)
+    }
+
+    fn account_of(pair: Option<sp_core::sr25519::Pair>) -> Option<String> {
+        pair.map(|pair| pair.public().into_account().to_string())
+    }
+
+    #[actix_web::test]
+    async fn keystore_upgrades_v1() {
+        let path = keystore_path();
+        let seed = account::generate_seed();
+        let legacy = vault::legacy_cipher_from_password("password");
+        let v1: HashMap<_, _> = [("alice", vault::seal(&legacy, &seed, &[]))].into_iter().collect();
+        std::fs::write(&path, serde_json::to_vec(&v1).unwrap()).unwrap();
+
+        let keystore = KeystoreSigner::open(path.clone(), "password").unwrap();
+        let expected = get_pair_from_seed(&seed).unwrap().public().into_account().to_string();
+        assert_eq!(account_of(keystore.pair("alice").await.unwrap()), Some(expected.clone()));
+
+        let file: KeystoreFile = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(file.version, KEYSTORE_VERSION);
+        let cipher = file.kdf.cipher("password").unwrap();
+        assert_eq!(vault::open(&cipher, &file.seeds["alice"], b"alice"), Some(seed));
+        assert_eq!(vault::open(&cipher, &file.seeds["alice"], &[]), None);
+
+        let reopened = KeystoreSigner::open(path.clone(), "password").unwrap();
+        assert_eq!(account_of(reopened.pair("alice").await.unwrap()), Some(expected));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn keystore_header_round_trip() {
+        let path = keystore_path();
+        let keystore = KeystoreSigner::open(path.clone(), "password").unwrap();
+        let account = keystore.create("alice").await.unwrap();
+
+        let file: KeystoreFile = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(file.version, KEYSTORE_VERSION);
+        assert_eq!(file.kdf, keystore.kdf);
+        assert_eq!(file.kdf.algorithm, vault::KeystoreKdf::ARGON2ID);
+
+        let reopened = KeystoreSigner::open(path.clone(), "password").unwrap();
+        assert_eq!(reopened.kdf, keystore.kdf);
+        assert_eq!(account_of(reopened.pair("alice").await.unwrap()), Some(account));
+        assert!(KeystoreSigner::open(path.clone(), "other").is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn keystore_create_and_transfer() {
+        let path = keystore_path();
+        let keystore = KeystoreSigner::open(path.clone(), "password").unwrap();
+        let alice = keystore.create("alice").await.unwrap();
+        let bob = keystore.create("bob").await.unwrap();
+        assert_ne!(alice, bob);
+
+        let err = keystore.transfer("alice", "bob").await.unwrap_err();
+        assert_eq!(err.as_response_error().status_code(), StatusCode::CONFLICT);
+        assert_eq!(account_of(keystore.pair("bob").await.unwrap()), Some(bob));
+
+        keystore.transfer("alice", "carol").await.unwrap();
+        assert!(keystore.pair("alice").await.unwrap().is_none());
+        assert!(keystore.transfer("alice", "dave").await.is_err());
+
+        let reopened = KeystoreSigner::open(path.clone(), "password").unwrap();
+        assert!(reopened.pair("alice").await.unwrap().is_none());
+        assert_eq!(account_of(reopened.pair("carol").await.unwrap()), Some(alice));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn dev_create_and_transfer() {
+        let dev = DevSigner::default();
+        let alice = dev.create("alice").await.unwrap();
+        let bob = dev.create("bob").await.unwrap();
+        assert_eq!(alice, AccountKeyring::Alice.to_account_id().to_string());
+        assert_eq!(bob, AccountKeyring::Bob.to_account_id().to_string());
+
+        let err = dev.transfer("alice", "bob").await.unwrap_err();
+        assert_eq!(err.as_response_error().status_code(), StatusCode::CONFLICT);
+        assert_eq!(account_of(dev.pair("bob").await.unwrap()), Some(bob));
+
+        dev.transfer("alice", "carol").await.unwrap();
+        assert!(dev.pair("alice").await.unwrap().is_none());
+        assert_eq!(account_of(dev.pair("carol").await.unwrap()), Some(alice));
+        assert!(dev.transfer("alice", "dave").await.is_err());
+    }
+}
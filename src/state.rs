@@ -1,9 +1,39 @@
+use crate::blob::BlobStore;
+use crate::client::{ClientPool, NonceManager};
+use crate::db::Database;
+use crate::signer::SignerBackend;
 use crate::sugarfunge;
-use std::sync::{Arc, Mutex};
+use crate::tx::JobStore;
+use crate::user::TokenManager;
+use std::sync::Arc;
+use subxt::PairSigner;
 
-pub type ClientAPI = Arc<Mutex<sugarfunge::RuntimeApi<sugarfunge::DefaultConfig>>>;
+pub type RuntimeApi = sugarfunge::RuntimeApi<sugarfunge::DefaultConfig>;
+
+/// Client of the pool, clients are never dropped
+pub type ClientAPI = &'static RuntimeApi;
+
+pub type TxProgress<'a> = subxt::TransactionProgress<'a, sugarfunge::DefaultConfig>;
+
+pub type TxEvents = subxt::TransactionEvents<sugarfunge::DefaultConfig>;
+
+pub type TxSigner = PairSigner<
+    sugarfunge::DefaultConfig,
+    subxt::DefaultExtra<sugarfunge::DefaultConfig>,
+    sp_core::sr25519::Pair,
+>;
 
 #[derive(Clone)]
 pub struct AppState {
-    pub api: ClientAPI,
+    pub api: Arc<ClientPool>,
+    pub nonces: Arc<NonceManager>,
+    pub jobs: Arc<JobStore>,
+    pub signer: Arc<dyn SignerBackend>,
+    pub tokens: Arc<TokenManager>,
+    pub db: Option<Database>,
+    pub blobs: Option<Arc<BlobStore>>,
+    /// Held by `currency/issue` while checking and submitting a capped issue
+    pub issuance: Arc<tokio::sync::Mutex<()>>,
+    /// Held by `asset/holders/check` while it runs, with the time of the last check
+    pub holders_check: Arc<tokio::sync::Mutex<Option<std::time::Instant>>>,
 }
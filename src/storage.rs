@@ -0,0 +1,152 @@
+use crate::state::*;
+use codec::{Decode, Encode};
+use sp_core::hashing::{blake2_128, twox_128};
+use sp_core::storage::StorageKey;
+use sp_core::H256;
+
+/// Prefix of every key of a storage map, `twox128(pallet) ++ twox128(storage)`
+pub fn storage_prefix(pallet: &str, storage: &str) -> Vec<u8> {
+    let mut prefix = twox_128(pallet.as_bytes()).to_vec();
+    prefix.extend(twox_128(storage.as_bytes()));
+    prefix
+}
+
+/// Key part of a `Blake2_128Concat` hasher
+pub fn blake2_128_concat<T: Encode>(value: &T) -> Vec<u8> {
+    let encoded = value.encode();
+    let mut key = blake2_128(&encoded).to_vec();
+    key.extend(encoded);
+    key
+}
+
+/// Decode a `Blake2_128Concat` key part, returns the value and the rest of the key
+pub fn decode_blake2_128_concat<T: Decode>(key: &[u8]) -> Option<(T, &[u8])> {
+    let mut rest = key.get(16..)?;
+    let value = T::decode(&mut rest).ok()?;
+    Some((value, rest))
+}
+
+/// Decode a `Twox64Concat` key part, returns the value and the rest of the key
+pub fn decode_twox_64_concat<T: Decode>(key: &[u8]) -> Option<(T, &[u8])> {
+    let mut rest = key.get(8..)?;
+    let value = T::decode(&mut rest).ok()?;
+    Some((value, rest))
+}
+
+pub struct StoragePage {
+    /// Keys and SCALE encoded values, in key order
+    pub entries: Vec<(Vec<u8>, Vec<u8>)>,
+    /// Key to continue from, `None` on the last page
+    pub next: Option<Vec<u8>>,
+}
+
+/// One page of the entries of a storage map under `prefix`, after `start_key`
+pub async fn entries_page(
+    api: &RuntimeApi,
+    prefix: &[u8],
+    count: u32,
+    start_key: Option<Vec<u8>>,
+    at: Option<H256>,
+) -> Result<StoragePage, subxt::Error> {
+    let keys = api
+        .client
+        .rpc()
+        .storage_keys_paged(
+            Some(StorageKey(prefix.to_vec())),
+            count,
+            start_key.map(StorageKey),
+            at,
+        )
+        .await?;
+    let next = match keys.last() {
+        Some(key) if keys.len() == count as usize => Some(key.0.clone()),
+        _ => None,
+    };
+    if keys.is_empty() {
+        return Ok(StoragePage {
+            entries: vec![],
+            next,
+        });
+    }
+    let change_sets = api.client.rpc().query_storage_at(&keys, at).await?;
+    let mut entries: Vec<(Vec<u8>, Vec<u8>)> = change_sets
+        .into_iter()
+        .flat_map(|change_set| change_set.changes)
+        .filter_map(|(key, value)| value.map(|value| (key.0, value.0)))
+        .collect();
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(StoragePage { entries, next })
+}
+
+/// Parse a pagination cursor, the hex storage key of the last returned entry
+pub fn parse_cursor(cursor: &Option<String>) -> Result<Option<Vec<u8>>, hex::FromHexError> {
+    cursor
+        .as_ref()
+        .map(|cursor| hex::decode(cursor.trim_start_matches("0x")))
+        .transpose()
+}
+
+pub fn format_cursor(key: &[u8]) -> String {
+    format!("0x{}", hex::encode(key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sp_core::crypto::AccountId32;
+    use sp_core::hashing::twox_64;
+
+    #[test]
+    fn storage_prefix_of_system_account() {
+        assert_eq!(
+            hex::encode(storage_prefix("System", "Account")),
+            "26aa394eea5630e07c48ae0c9558cef7b99d880ec681799c0cf30e8886371da9"
+        );
+    }
+
+    #[test]
+    fn blake2_128_concat_round_trip() {
+        let account = AccountId32::new([7; 32]);
+        let class_id = 42u64;
+        let mut key = blake2_128_concat(&account);
+        key.extend(blake2_128_concat(&class_id));
+        assert_eq!(key.len(), 16 + 32 + 16 + 8);
+
+        let (decoded, rest) = decode_blake2_128_concat::<AccountId32>(&key).unwrap();
+        assert_eq!(decoded, account);
+        let (decoded, rest) = decode_blake2_128_concat::<u64>(rest).unwrap();
+        assert_eq!(decoded, class_id);
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn twox_64_concat_round_trip() {
+        let value = (1u64, 2u64);
+        let mut key = twox_64(&value.encode()).to_vec();
+        key.extend(value.encode());
+        key.push(9);
+
+        let (decoded, rest) = decode_twox_64_concat::<(u64, u64)>(&key).unwrap();
+        assert_eq!(decoded, value);
+        assert_eq!(rest, &[9]);
+    }
+
+    #[test]
+    fn decode_short_keys() {
+        assert!(decode_blake2_128_concat::<u64>(&[0; 15]).is_none());
+        assert!(decode_blake2_128_concat::<u64>(&[0; 20]).is_none());
+        assert!(decode_twox_64_concat::<u64>(&[0; 7]).is_none());
+        assert!(decode_twox_64_concat::<u64>(&[0; 12]).is_none());
+    }
+
+    #[test]
+    fn cursor_round_trip() {
+        let key = storage_prefix("Asset", "Balances");
+        let cursor = format_cursor(&key);
+        assert!(cursor.starts_with("0x"));
+        assert_eq!(parse_cursor(&Some(cursor)).unwrap(), Some(key.clone()));
+        assert_eq!(parse_cursor(&Some(hex::encode(&key))).unwrap(), Some(key));
+        assert_eq!(parse_cursor(&None).unwrap(), None);
+        assert!(parse_cursor(&Some("0xzz".to_string())).is_err());
+    }
+}
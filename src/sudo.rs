@@ -0,0 +1,135 @@
+use crate::audit;
+use crate::scale;
+use crate::signer;
+use crate::state::*;
+use crate::sugarfunge;
+use crate::tx;
+use crate::user;
+use crate::util::*;
+use actix_web::{error, web, HttpResponse};
+use actix_web_middleware_keycloak_auth::KeycloakClaims;
+use codec::Decode;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sp_core::Pair;
+use std::str::FromStr;
+use sugarfunge::runtime_types::sugarfunge_runtime::Call;
+
+#[derive(Serialize, Deserialize)]
+pub struct SudoCallInput {
+    pallet: String,
+    call: String,
+    /// Call arguments keyed by argument name
+    args: Option<serde_json::Value>,
+    /// Dispatch the call from this account with `sudo_as` instead of root
+    as_account: Option<String>,
+    #[serde(default)]
+    dry_run: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SudoCallEncodedOutput {
+    call: String,
+    sudo_call: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SudoEventOutput {
+    pallet: String,
+    variant: String,
+    data: serde_json::Value,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SudoCallOutput {
+    call: String,
+    events: Vec<SudoEventOutput>,
+}
+
+fn encode_err(message: String) -> HttpResponse {
+    HttpResponse::BadRequest().json(RequestError {
+        message: json!(message),
+    })
+}
+
+/// Encode a call of any pallet from JSON arguments and submit it wrapped in
+/// `sudo`, or `sudo_as` when `as_account` is set
+pub async fn call(
+    data: web::Data<AppState>,
+    req: web::Json<SudoCallInput>,
+    query: web::Query<tx::WaitQuery>,
+    claims: KeycloakClaims<user::ClaimsWithEmail>,
+) -> error::Result<HttpResponse> {
+    let as_account = match &req.as_account {
+        Some(account) => {
+            let account = sp_core::sr25519::Public::from_str(account).map_err(map_account_err)?;
+            Some(sp_core::crypto::AccountId32::from(account))
+        }
+        None => None,
+    };
+    let metadata = scale::metadata().map_err(error::ErrorInternalServerError)?;
+    let args = req.args.clone().unwrap_or_else(|| json!({}));
+    let encoded = match scale::encode_call(metadata, &req.pallet, &req.call, &args) {
+        Ok(encoded) => encoded,
+        Err(message) => return Ok(encode_err(message)),
+    };
+    let call = match Call::decode(&mut &encoded[..]) {
+        Ok(call) => call,
+        Err(e) => return Ok(encode_err(format!("Encoded call does not match the runtime: {}", e))),
+    };
+
+    if req.dry_run {
+        let sudo_call = scale::encode_sudo(metadata, &encoded, as_account.as_ref())
+            .map_err(error::ErrorInternalServerError)?;
+        return Ok(HttpResponse::Ok().json(SudoCallEncodedOutput {
+            call: format!("0x{}", hex::encode(&encoded)),
+            sudo_call: format!("0x{}", hex::encode(&sudo_call)),
+        }));
+    }
+
+    let pair = signer::user_pair(&data, &claims.sub).await?;
+    let account = sp_core::crypto::AccountId32::from(pair.public());
+    let audit = audit::SudoAudit::start(
+        &data,
+        &claims.sub,
+        &account.to_string(),
+        &format!("{}.{}", req.pallet, req.call),
+        &json!({ "args": args, "as_account": req.as_account }),
+    )
+    .await?;
+    let signer = signer::tx_signer(&data, pair).await?;
+    let api = data.api.get();
+    let result = match as_account {
+        Some(who) => {
+            api.tx()
+                .sudo()
+                .sudo_as(subxt::sp_runtime::MultiAddress::Id(who), call)
+                .sign_and_submit_then_watch(&signer)
+                .await
+        }
+        None => {
+            api.tx()
+                .sudo()
+                .sudo(call)
+                .sign_and_submit_then_watch(&signer)
+                .await
+        }
+    };
+    let events = match audit::sudo_outcome(&data, &audit, &signer, result, query.wait).await? {
+        Ok(events) => events,
+        Err(response) => return Ok(response),
+    };
+    let events = events
+        .iter()
+        .map(|event| SudoEventOutput {
+            pallet: event.pallet.clone(),
+            variant: event.variant.clone(),
+            data: scale::decode_event(metadata, &event.pallet, &event.variant, &event.data.0)
+                .unwrap_or_else(|_| json!(format!("0x{}", hex::encode(&event.data.0)))),
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(SudoCallOutput {
+        call: format!("0x{}", hex::encode(&encoded)),
+        events,
+    }))
+}
@@ -0,0 +1,521 @@
+use crate::client::NonceManager;
+use crate::db::{map_db_err, Database, TransactionRecord};
+use crate::signer;
+use crate::state::*;
+use crate::user;
+use crate::util::*;
+use actix_web::{error, web, HttpResponse, HttpResponseBuilder};
+use actix_web_middleware_keycloak_auth::KeycloakClaims;
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use futures::future::LocalBoxFuture;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use subxt::{Signer, TransactionStatus};
+
+/// Jobs are kept this long after their last update
+const JOB_RETENTION: Duration = Duration::from_secs(60 * 60);
+
+/// Jobs still pending after this long are marked as failed
+const JOB_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// How long a transaction handler waits before answering
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Wait {
+    None,
+    InBlock,
+    Finalized,
+}
+
+impl Default for Wait {
+    fn default() -> Self {
+        Wait::Finalized
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct WaitQuery {
+    #[serde(default)]
+    pub wait: Wait,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "status")]
+pub enum TxStatus {
+    Ready,
+    InBlock { block_hash: String },
+    Finalized { block_hash: String },
+    Invalid,
+    /// Dropped, usurped, retracted or not finalized in time, see `error`
+    Failed,
+}
+
+impl TxStatus {
+    fn is_done(&self) -> bool {
+        !matches!(self, TxStatus::Ready | TxStatus::InBlock { .. })
+    }
+}
+
+/// Final state of a transaction submitted with `wait=none`
+pub enum JobOutcome {
+    /// Finalized and succeeded, with its events
+    Done(TxEvents),
+    /// Invalid, failed, dropped or not finalized in time
+    Failed(String),
+}
+
+/// Called once a transaction submitted with `wait=none` reaches its final state
+pub type OnDone = Box<dyn FnOnce(JobOutcome) -> LocalBoxFuture<'static, ()>>;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TxEventOutput {
+    pallet: String,
+    variant: String,
+    data: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TxJobOutput {
+    job_id: String,
+    tx_hash: String,
+    #[serde(flatten)]
+    status: TxStatus,
+    events: Option<Vec<TxEventOutput>>,
+    error: Option<serde_json::Value>,
+}
+
+struct TxJob {
+    account: String,
+    sub: String,
+    output: TxJobOutput,
+    updated: Instant,
+}
+
+impl TxJobOutput {
+    fn record(&self, account: &str, sub: &str) -> TransactionRecord {
+        let (status, block_hash) = match &self.status {
+            TxStatus::Ready => ("ready", None),
+            TxStatus::InBlock { block_hash } => ("in_block", Some(block_hash.clone())),
+            TxStatus::Finalized { block_hash } => ("finalized", Some(block_hash.clone())),
+            TxStatus::Invalid => ("invalid", None),
+            TxStatus::Failed => ("failed", None),
+        };
+        TransactionRecord {
+            job_id: self.job_id.clone(),
+            tx_hash: self.tx_hash.clone(),
+            account: account.to_string(),
+            sub: Some(sub.to_string()),
+            status: status.to_string(),
+            block_hash,
+            events: self.events.as_ref().map(|events| json!(events).to_string()),
+            error: self.error.as_ref().map(|error| error.to_string()),
+        }
+    }
+
+    /// Job of a previous run of the API. Jobs that did not reach a final state
+    /// are no longer watched, they are reported as failed.
+    fn from_record(record: TransactionRecord) -> Self {
+        let block_hash = record.block_hash.unwrap_or_default();
+        let status = match record.status.as_str() {
+            "finalized" => TxStatus::Finalized { block_hash },
+            "invalid" => TxStatus::Invalid,
+            _ => TxStatus::Failed,
+        };
+        let error = match (&status, record.error) {
+            (_, Some(error)) => serde_json::from_str(&error).ok(),
+            (TxStatus::Failed, None) => Some(json!("Transaction was no longer watched before reaching a final state")),
+            _ => None,
+        };
+        TxJobOutput {
+            job_id: record.job_id,
+            tx_hash: record.tx_hash,
+            status,
+            events: record.events.and_then(|events| serde_json::from_str(&events).ok()),
+            error,
+        }
+    }
+}
+
+/// Transactions submitted with `wait=none`, watched in the background.
+///
+/// Jobs are also written to the `transactions` table when a database is
+/// configured, so their status survives restarts of the API.
+#[derive(Default)]
+pub struct JobStore {
+    jobs: Mutex<HashMap<String, TxJob>>,
+    db: Option<Database>,
+}
+
+impl JobStore {
+    pub fn new(db: Option<Database>) -> Self {
+        JobStore {
+            jobs: Mutex::new(HashMap::new()),
+            db,
+        }
+    }
+
+    async fn persist(&self, account: &str, sub: &str, output: &TxJobOutput) {
+        if let Some(db) = &self.db {
+            if let Err(e) = db.save_transaction(&output.record(account, sub)).await {
+                log::error!("Failed to save transaction job {}: {}", output.job_id, e);
+            }
+        }
+    }
+
+    async fn insert(&self, account: String, sub: String, output: TxJobOutput) {
+        self.persist(&account, &sub, &output).await;
+        let mut jobs = self.jobs.lock().unwrap();
+        // Pending jobs are updated at the latest after JOB_TIMEOUT, so this also
+        // drops the jobs of watchers that stopped without a final state
+        jobs.retain(|_, job| job.updated.elapsed() < JOB_RETENTION);
+        jobs.insert(
+            output.job_id.clone(),
+            TxJob {
+                account,
+                sub,
+                output,
+                updated: Instant::now(),
+            },
+        );
+    }
+
+    async fn update<F: FnOnce(&mut TxJobOutput)>(&self, job_id: &str, f: F) {
+        let updated = match self.jobs.lock().unwrap().get_mut(job_id) {
+            Some(job) => {
+                f(&mut job.output);
+                job.updated = Instant::now();
+                Some((job.account.clone(), job.sub.clone(), job.output.clone()))
+            }
+            None => None,
+        };
+        if let Some((account, sub, output)) = updated {
+            self.persist(&account, &sub, &output).await;
+        }
+    }
+
+    /// Job submitted by the Keycloak user `sub`
+    pub async fn get(&self, job_id: &str, sub: &str) -> Result<Option<TxJobOutput>, sqlx::Error> {
+        let job = self
+            .jobs
+            .lock()
+            .unwrap()
+            .get(job_id)
+            .map(|job| (job.sub.clone(), job.output.clone()));
+        match (job, &self.db) {
+            (Some((job_sub, job)), _) => Ok(Some(job).filter(|_| job_sub == sub)),
+            (None, Some(db)) => Ok(db
+                .transaction(job_id)
+                .await?
+                .filter(|record| record.sub.as_deref() == Some(sub))
+                .map(TxJobOutput::from_record)),
+            (None, None) => Ok(None),
+        }
+    }
+}
+
+pub enum Submitted {
+    /// The transaction is being watched in the background
+    Pending(TxJobOutput),
+    /// The transaction was included with the requested finality
+    Done(TxEvents),
+}
+
+fn events_output(events: &TxEvents) -> Vec<TxEventOutput> {
+    events
+        .iter()
+        .map(|event| TxEventOutput {
+            pallet: event.pallet.clone(),
+            variant: event.variant.clone(),
+            data: format!("0x{}", hex::encode(&event.data.0)),
+        })
+        .collect()
+}
+
+/// Wait for a transaction submitted by the Keycloak user `sub` according to `wait`
+pub async fn wait(
+    data: &AppState,
+    sub: &str,
+    signer: &TxSigner,
+    progress: TxProgress<'static>,
+    wait: Wait,
+) -> error::Result<Submitted> {
+    try_wait(data, sub, signer, progress, wait).await.map_err(map_subxt_err)
+}
+
+/// Same as `wait`, returns the error of the node or of the dispatch
+pub async fn try_wait(
+    data: &AppState,
+    sub: &str,
+    signer: &TxSigner,
+    progress: TxProgress<'static>,
+    wait: Wait,
+) -> Result<Submitted, subxt::Error> {
+    try_wait_then(data, sub, signer, progress, wait, None).await
+}
+
+/// Same as `try_wait`, `on_done` is called when a `wait=none` transaction
+/// reaches its final state
+pub async fn try_wait_then(
+    data: &AppState,
+    sub: &str,
+    signer: &TxSigner,
+    mut progress: TxProgress<'static>,
+    wait: Wait,
+    on_done: Option<OnDone>,
+) -> Result<Submitted, subxt::Error> {
+    let reset = |e: subxt::Error| {
+        data.nonces.reset(signer.account_id());
+        e
+    };
+    match wait {
+        Wait::Finalized => {
+            let events = progress.wait_for_finalized_success().await.map_err(reset)?;
+            Ok(Submitted::Done(events))
+        }
+        Wait::InBlock => {
+            let events = progress
+                .wait_for_in_block()
+                .await
+                .map_err(reset)?
+                .wait_for_success()
+                .await?;
+            Ok(Submitted::Done(events))
+        }
+        Wait::None => {
+            let job = TxJobOutput {
+                job_id: hex::encode(rand::thread_rng().gen::<[u8; 16]>()),
+                tx_hash: format!("{:?}", progress.extrinsic_hash()),
+                status: TxStatus::Ready,
+                events: None,
+                error: None,
+            };
+            let account = signer.account_id().clone();
+            data.jobs.insert(account.to_string(), sub.to_string(), job.clone()).await;
+
+            let jobs = data.jobs.clone();
+            let nonces = data.nonces.clone();
+            let job_id = job.job_id.clone();
+            actix_web::rt::spawn(async move {
+                let watched = watch(&jobs, &nonces, &account, &job_id, &mut progress);
+                let outcome = match actix_web::rt::time::timeout(JOB_TIMEOUT, watched).await {
+                    Ok(outcome) => outcome,
+                    Err(_) => {
+                        let error = json!("Timed out waiting for the transaction to be finalized");
+                        fail(&jobs, &nonces, &account, &job_id, error).await
+                    }
+                };
+                if let Some(on_done) = on_done {
+                    on_done(outcome).await;
+                }
+            });
+            Ok(Submitted::Pending(job))
+        }
+    }
+}
+
+/// Largest number of transactions in a batch request
+pub const BATCH_MAX_ITEMS: usize = 1000;
+
+pub enum BatchOutcome {
+    Done(TxEvents),
+    Pending(TxJobOutput),
+    Failed(serde_json::Value),
+}
+
+#[derive(Serialize)]
+pub struct BatchItemOutput<T: Serialize> {
+    index: usize,
+    event: Option<T>,
+    job: Option<TxJobOutput>,
+    error: Option<serde_json::Value>,
+}
+
+/// Submit one transaction per item through `api`, with consecutive nonces of
+/// `pair`, then wait for all of them according to `wait`
+pub async fn submit_batch<I, F, Fut>(
+    data: &AppState,
+    sub: &str,
+    api: ClientAPI,
+    pair: &sp_core::sr25519::Pair,
+    items: Vec<I>,
+    wait: Wait,
+    submit: F,
+) -> Vec<BatchOutcome>
+where
+    F: Fn(ClientAPI, TxSigner, I) -> Fut,
+    Fut: Future<Output = (TxSigner, Result<TxProgress<'static>, subxt::Error>)>,
+{
+    let mut submitted = Vec::with_capacity(items.len());
+    for item in items {
+        let signer = match signer::tx_signer(data, pair.clone()).await {
+            Ok(signer) => signer,
+            Err(e) => {
+                submitted.push(Err(json!(e.to_string())));
+                continue;
+            }
+        };
+        match submit(api, signer, item).await {
+            (signer, Ok(progress)) => submitted.push(Ok((signer, progress))),
+            (signer, Err(e)) => {
+                data.nonces.reset(signer.account_id());
+                submitted.push(Err(subxt_err_message(e)));
+            }
+        }
+    }
+    let outcomes = submitted.into_iter().map(|submitted| async move {
+        let (signer, progress) = match submitted {
+            Ok(submitted) => submitted,
+            Err(e) => return BatchOutcome::Failed(e),
+        };
+        match try_wait(data, sub, &signer, progress, wait).await {
+            Ok(Submitted::Done(events)) => BatchOutcome::Done(events),
+            Ok(Submitted::Pending(job)) => BatchOutcome::Pending(job),
+            Err(e) => BatchOutcome::Failed(subxt_err_message(e)),
+        }
+    });
+    futures::future::join_all(outcomes).await
+}
+
+/// `202 Accepted` when the transactions are only submitted, `200 OK` otherwise
+pub fn batch_response(wait: Wait) -> HttpResponseBuilder {
+    match wait {
+        Wait::None => HttpResponse::Accepted(),
+        _ => HttpResponse::Ok(),
+    }
+}
+
+/// Per item output of a batch, `event` is built from the events of each transaction
+pub fn batch_output<T, F>(outcomes: Vec<BatchOutcome>, event_name: &str, event: F) -> Vec<BatchItemOutput<T>>
+where
+    T: Serialize,
+    F: Fn(&TxEvents) -> Result<Option<T>, subxt::Error>,
+{
+    outcomes
+        .into_iter()
+        .enumerate()
+        .map(|(index, outcome)| {
+            let mut output = BatchItemOutput {
+                index,
+                event: None,
+                job: None,
+                error: None,
+            };
+            match outcome {
+                BatchOutcome::Done(events) => match event(&events) {
+                    Ok(Some(event)) => output.event = Some(event),
+                    Ok(None) => output.error = Some(json!(format!("Failed to find {}", event_name))),
+                    Err(e) => output.error = Some(subxt_err_message(e)),
+                },
+                BatchOutcome::Pending(job) => output.job = Some(job),
+                BatchOutcome::Failed(e) => output.error = Some(e),
+            }
+            output
+        })
+        .collect()
+}
+
+/// Mark a job as failed, its nonce may not have been used
+async fn fail(
+    jobs: &Arc<JobStore>,
+    nonces: &Arc<NonceManager>,
+    account: &sp_core::crypto::AccountId32,
+    job_id: &str,
+    error: serde_json::Value,
+) -> JobOutcome {
+    nonces.reset(account);
+    let message = error.as_str().map(String::from).unwrap_or_else(|| error.to_string());
+    jobs.update(job_id, |job| {
+        job.status = TxStatus::Failed;
+        job.error = Some(error);
+    })
+    .await;
+    JobOutcome::Failed(message)
+}
+
+async fn watch(
+    jobs: &Arc<JobStore>,
+    nonces: &Arc<NonceManager>,
+    account: &sp_core::crypto::AccountId32,
+    job_id: &str,
+    progress: &mut TxProgress<'static>,
+) -> JobOutcome {
+    while let Some(status) = progress.next_item().await {
+        let (status, in_block) = match status {
+            Ok(TransactionStatus::InBlock(in_block)) => (
+                TxStatus::InBlock {
+                    block_hash: format!("{:?}", in_block.block_hash()),
+                },
+                Some(in_block),
+            ),
+            Ok(TransactionStatus::Finalized(in_block)) => (
+                TxStatus::Finalized {
+                    block_hash: format!("{:?}", in_block.block_hash()),
+                },
+                Some(in_block),
+            ),
+            Ok(TransactionStatus::Invalid) => (TxStatus::Invalid, None),
+            Ok(TransactionStatus::Dropped) => {
+                return fail(jobs, nonces, account, job_id, json!("Transaction was dropped from the pool")).await
+            }
+            Ok(TransactionStatus::Usurped(hash)) => {
+                let error = json!(format!("Transaction was replaced by {:?}", hash));
+                return fail(jobs, nonces, account, job_id, error).await;
+            }
+            Ok(TransactionStatus::Retracted(hash)) => {
+                let error = json!(format!("Block {:?} with the transaction was retracted", hash));
+                return fail(jobs, nonces, account, job_id, error).await;
+            }
+            Ok(TransactionStatus::FinalityTimeout(hash)) => {
+                let error = json!(format!("Block {:?} with the transaction was not finalized in time", hash));
+                return fail(jobs, nonces, account, job_id, error).await;
+            }
+            Ok(_) => continue,
+            Err(e) => return fail(jobs, nonces, account, job_id, subxt_err_message(e)).await,
+        };
+        if matches!(status, TxStatus::Invalid) {
+            nonces.reset(account);
+        }
+        let done = status.is_done();
+        let (events, error, outcome) = match in_block {
+            Some(in_block) => match in_block.wait_for_success().await {
+                Ok(events) => (Some(events_output(&events)), None, JobOutcome::Done(events)),
+                Err(e) => {
+                    let error = subxt_err_message(e);
+                    let message = error.as_str().map(String::from).unwrap_or_else(|| error.to_string());
+                    (None, Some(error), JobOutcome::Failed(message))
+                }
+            },
+            None => (None, None, JobOutcome::Failed("Transaction is invalid".to_string())),
+        };
+        jobs.update(job_id, |job| {
+            job.status = status;
+            if events.is_some() {
+                job.events = events;
+            }
+            job.error = error;
+        })
+        .await;
+        if done {
+            return outcome;
+        }
+    }
+    fail(jobs, nonces, account, job_id, json!("Transaction status subscription closed")).await
+}
+
+/// Get the status of a transaction submitted with `wait=none`
+pub async fn status(
+    data: web::Data<AppState>,
+    job_id: web::Path<String>,
+    claims: KeycloakClaims<user::ClaimsWithEmail>,
+) -> error::Result<HttpResponse> {
+    match data.jobs.get(&job_id, &claims.sub).await.map_err(map_db_err)? {
+        Some(job) => Ok(HttpResponse::Ok().json(job)),
+        None => Ok(HttpResponse::NotFound().json(RequestError {
+            message: json!("Transaction job not found"),
+        })),
+    }
+}
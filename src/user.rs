@@ -1,14 +1,18 @@
-use crate::account;
 use crate::config::Config;
+use crate::vault;
+use crate::db::{map_db_err, require_db};
+use crate::state::*;
 use serde::{Deserialize, Serialize};
 use actix_web::{
     web,
+    HttpResponse,
     Responder,
-    HttpRequest,
-    body,
     http::{header, StatusCode}
 };
 use serde_json::json;
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
 use awc::{self};
 use actix_web_middleware_keycloak_auth::{KeycloakClaims};
 
@@ -22,61 +26,165 @@ struct Credentials {
     scope: String
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+struct RefreshCredentials {
+    grant_type: String,
+    client_id: String,
+    client_secret: String,
+    refresh_token: String
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct SugarTokenOutput {
     pub access_token: String,
+    #[serde(default)]
+    pub expires_in: u64,
+    #[serde(default)]
+    pub refresh_token: Option<String>,
+    #[serde(default)]
+    pub refresh_expires_in: u64,
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct ErrorMessageOutput {
-    error: String,
-    message: String
-}
+/// Refresh tokens this long before they expire
+const TOKEN_REFRESH_MARGIN: Duration = Duration::from_secs(30);
 
-pub async fn get_sugarfunge_token(env: web::Data<Config>) -> Result<SugarTokenOutput, impl Responder> {
-    let config = &env;
+async fn request_token<T: Serialize>(config: &Config, form: &T) -> Result<SugarTokenOutput, ()> {
     let endpoint = config.keycloak_host.to_string() + "/auth/realms/" + &config.keycloak_realm + "/protocol/openid-connect/token";
 
-    let credentials = web::Data::new(Credentials{
+    let awc_client = awc::Client::new();
+
+    let response = awc_client.post(endpoint)
+        .insert_header((header::CONTENT_TYPE, "application/x-www-form-urlencoded"))
+        .send_form(form)
+        .await; 
+
+    match response {
+        Ok(mut response) if response.status() == StatusCode::OK => {
+            response.json::<SugarTokenOutput>().await.map_err(|_| ())
+        },
+        _ => Err(())
+    }
+}
+
+/// Token of the sugarfunge service user using the password grant
+pub async fn get_sugarfunge_token(config: &Config) -> Result<SugarTokenOutput, ()> {
+    let credentials = Credentials{
         client_id: config.keycloak_client_id.to_string(),
         grant_type: "password".to_string(),
         username: config.keycloak_username.to_owned(),
         password: config.keycloak_user_password.to_owned(),
         client_secret: config.keycloak_client_secret.to_owned(),
         scope: "openid".to_string()
-    });
+    };
+    request_token(config, &credentials).await
+}
 
-    let awc_client = awc::Client::new();
+async fn refresh_sugarfunge_token(config: &Config, refresh_token: &str) -> Result<SugarTokenOutput, ()> {
+    let credentials = RefreshCredentials{
+        client_id: config.keycloak_client_id.to_string(),
+        grant_type: "refresh_token".to_string(),
+        client_secret: config.keycloak_client_secret.to_owned(),
+        refresh_token: refresh_token.to_string()
+    };
+    request_token(config, &credentials).await
+}
 
-    let response = awc_client.post(endpoint)
-        .insert_header((header::CONTENT_TYPE, "application/x-www-form-urlencoded"))
-        .send_form(&credentials)
-        .await; 
+struct AdminToken {
+    access_token: String,
+    expires_at: Instant,
+    refresh_token: Option<String>,
+    refresh_expires_at: Instant,
+}
 
-    match response {
-        Ok(mut response) => {
-            match response.status() {
-                StatusCode::OK => {
-                    let body_str: String = std::str::from_utf8(&response.body().await.unwrap()).unwrap().to_string();
-                    let body: SugarTokenOutput = serde_json::from_str(&body_str).unwrap();
-                    Ok(body)
-                },
-                _ => {
-                    Err(web::Json(
-                        ErrorMessageOutput {
-                            error: "Error request".to_string(),
-                            message: "Error when requesting token".to_string()
-                        }
-                    ))
-                }
+impl AdminToken {
+    fn new(token: SugarTokenOutput) -> Self {
+        let now = Instant::now();
+        AdminToken {
+            access_token: token.access_token,
+            expires_at: now + Duration::from_secs(token.expires_in),
+            refresh_token: token.refresh_token,
+            refresh_expires_at: now + Duration::from_secs(token.refresh_expires_in),
+        }
+    }
+}
+
+pub enum AdminError {
+    Unauthorized,
+    Failed,
+}
+
+/// Caches the access token of the sugarfunge service user for the Keycloak admin API
+pub struct TokenManager {
+    pub config: web::Data<Config>,
+    token: Mutex<Option<AdminToken>>,
+}
+
+impl TokenManager {
+    pub fn new(config: web::Data<Config>) -> Self {
+        TokenManager {
+            config,
+            token: Mutex::new(None),
+        }
+    }
+
+    /// Get a valid access token, refreshing it before it expires.
+    /// Concurrent callers wait for the same refresh instead of requesting their own token.
+    pub async fn access_token(&self) -> Result<String, ()> {
+        let mut token = self.token.lock().await;
+        let now = Instant::now();
+        if let Some(current) = token.as_ref() {
+            if current.expires_at > now + TOKEN_REFRESH_MARGIN {
+                return Ok(current.access_token.clone());
             }
-        },
-        Err(_) => Err(web::Json(
-            ErrorMessageOutput {
-                error: "Unknown".to_string(),
-                message: "Error Unknown".to_string()
+        }
+        let refresh_token = token
+            .as_ref()
+            .filter(|current| current.refresh_expires_at > now + TOKEN_REFRESH_MARGIN)
+            .and_then(|current| current.refresh_token.clone());
+        let fresh = match refresh_token {
+            Some(refresh_token) => match refresh_sugarfunge_token(&self.config, &refresh_token).await {
+                Ok(fresh) => fresh,
+                Err(_) => get_sugarfunge_token(&self.config).await?,
+            },
+            None => get_sugarfunge_token(&self.config).await?,
+        };
+        let fresh = AdminToken::new(fresh);
+        let access_token = fresh.access_token.clone();
+        *token = Some(fresh);
+        Ok(access_token)
+    }
+
+    /// Forget `access_token` if it is still the cached one
+    pub async fn invalidate(&self, access_token: &str) {
+        let mut token = self.token.lock().await;
+        if token.as_ref().map(|current| current.access_token == access_token).unwrap_or(false) {
+            *token = None;
+        }
+    }
+
+    /// Run an admin API request, retrying once with a new token if Keycloak answers 401
+    pub async fn with_token<T, F, Fut>(&self, request: F) -> Result<T, ()>
+    where
+        F: Fn(String) -> Fut,
+        Fut: Future<Output = Result<T, AdminError>>,
+    {
+        let access_token = self.access_token().await?;
+        match request(access_token.clone()).await {
+            Ok(result) => Ok(result),
+            Err(AdminError::Unauthorized) => {
+                self.invalidate(&access_token).await;
+                let access_token = self.access_token().await?;
+                request(access_token).await.map_err(|_| ())
             }
-        ))
+            Err(AdminError::Failed) => Err(()),
+        }
+    }
+}
+
+fn admin_status(status: StatusCode) -> AdminError {
+    match status {
+        StatusCode::UNAUTHORIZED => AdminError::Unauthorized,
+        _ => AdminError::Failed,
     }
 }
 
@@ -85,6 +193,7 @@ pub async fn get_sugarfunge_token(env: web::Data<Config>) -> Result<SugarTokenOu
 pub struct UserInfo {
     id: String,
     attributes: Option<UserAtributes>,
+    #[serde(default)]
     email: String,
     #[serde(rename = "emailVerified", default)]
     email_verified: bool,
@@ -98,9 +207,32 @@ pub struct UserInfo {
 #[derive(Debug,Serialize, Deserialize, Default, Clone)]
 pub struct UserAtributes {
     #[serde(rename = "user-seed", default)]
-    user_seed: Box<[String]>
+    user_seed: Box<[String]>,
+    /// Attributes set by other applications, kept when the seed is updated
+    #[serde(flatten)]
+    other: serde_json::Map<String, serde_json::Value>
+}
+
+
+/// Decrypt the stored `user-seed` attribute of `user_id`
+fn open_seed(config: &Config, user_id: &str, stored: &str) -> Option<String> {
+    match &config.seed_master_keys {
+        Some(keys) => keys.open(stored, user_id),
+        None if vault::is_sealed(stored) => {
+            log::error!("Seed of user {} is encrypted but SEED_MASTER_KEYS is not set", user_id);
+            None
+        }
+        None => Some(stored.to_string())
+    }
 }
 
+/// Encrypt a seed before storing it as `user-seed` attribute of `user_id`
+fn seal_seed(config: &Config, user_id: &str, seed: &str) -> String {
+    match &config.seed_master_keys {
+        Some(keys) => keys.seal(seed, user_id),
+        None => seed.to_string()
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UserSeedOutput {
@@ -108,66 +240,61 @@ pub struct UserSeedOutput {
 }
 
 
-pub async fn get_seed(
-    user_id: &String,
-    env: web::Data<Config>
-) -> Result<web::Json<UserSeedOutput>, web::Json<UserSeedOutput>> { 
-    let config = env.clone();
+async fn get_user_info(user_id: &String, tokens: &TokenManager) -> Result<UserInfo, ()> {
+    let config = &tokens.config;
+    let endpoint = format!("{}/auth/admin/realms/{}/users/{}", config.keycloak_host, config.keycloak_realm, user_id); 
 
-    match get_sugarfunge_token(env).await {
-        Ok(response) => {
-            // println!("{:?}", response.access_token);
+    tokens.with_token(|access_token| {
+        let endpoint = endpoint.clone();
+        async move {
             let awc_client = awc::Client::new();
-            let endpoint = format!("{}/auth/admin/realms/{}/users/{}", config.keycloak_host, config.keycloak_realm, user_id); 
-
             let user_response = awc_client.get(endpoint)
                 .append_header((header::ACCEPT, "application/json"), )
                 .append_header((header::CONTENT_TYPE, "application/json"))
-                .append_header((header::AUTHORIZATION, "Bearer ".to_string() + &response.access_token))
+                .append_header((header::AUTHORIZATION, "Bearer ".to_string() + &access_token))
                 .send()
                 .await; 
 
             match user_response {
-                Ok(mut user_response) => {
-                    
-                    match user_response.status() {
-                        StatusCode::OK => {
-                            let body_str: String = std::str::from_utf8(&user_response.body().await.unwrap()).unwrap().to_string();
-                            let user_info: UserInfo = serde_json::from_str(&body_str).unwrap();
-
-                            // println!("{:?}", &user_info);
-                            if !user_info.attributes.clone().unwrap_or_default().user_seed.is_empty() {
-                                let user_seed = user_info.attributes.clone().unwrap_or_default().user_seed[0].clone();
-                                Ok(web::Json(
-                                    UserSeedOutput {
-                                        seed: Some(user_seed)
-                                    }
-                                ))
-                            } else {
-                                Ok(web::Json(
-                                    UserSeedOutput {
-                                        seed: Some("".to_string())
-                                    }
-                                ))
-                            }
-                        },
-                        _ => Err(web::Json(
-                            UserSeedOutput {
-                                seed: None
-                            }
-                        ))
-                    }
-                }
-                Err(_) => Err(web::Json(
+                Ok(mut user_response) => match user_response.status() {
+                    StatusCode::OK => user_response.json::<UserInfo>().await.map_err(|_| AdminError::Failed),
+                    status => Err(admin_status(status))
+                },
+                Err(_) => Err(AdminError::Failed)
+            }
+        }
+    }).await
+}
+
+pub async fn get_seed(
+    user_id: &String,
+    tokens: &TokenManager
+) -> Result<web::Json<UserSeedOutput>, web::Json<UserSeedOutput>> { 
+    match get_user_info(user_id, tokens).await {
+        Ok(user_info) => {
+            if !user_info.attributes.clone().unwrap_or_default().user_seed.is_empty() {
+                let user_seed = user_info.attributes.clone().unwrap_or_default().user_seed[0].clone();
+                match open_seed(&tokens.config, user_id, &user_seed) {
+                    Some(user_seed) => Ok(web::Json(
+                        UserSeedOutput {
+                            seed: Some(user_seed)
+                        }
+                    )),
+                    None => Err(web::Json(
                         UserSeedOutput {
                             seed: None
                         }
                     ))
                 }
-
-
+            } else {
+                Ok(web::Json(
+                    UserSeedOutput {
+                        seed: Some("".to_string())
+                    }
+                ))
+            }
         }
-        Err(_e) => Err(web::Json(
+        Err(_) => Err(web::Json(
             UserSeedOutput {
                 seed: None
             }
@@ -182,100 +309,158 @@ pub struct InsertUserSeedOutput {
     message: String
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct CreateAccountOutput {
-    seed: String,
-    account: String,
+async fn put_user_attributes(
+    user_id: &String,
+    attributes: &serde_json::Value,
+    tokens: &TokenManager
+) -> Result<(), ()> {
+    let config = &tokens.config;
+    let endpoint = format!("{}/auth/admin/realms/{}/users/{}", config.keycloak_host, config.keycloak_realm, user_id);
+
+    tokens.with_token(|access_token| {
+        let endpoint = endpoint.clone();
+        async move {
+            let awc_client = awc::Client::new();
+            let response = awc_client.put(endpoint)
+                .append_header((header::ACCEPT, "application/json"), )
+                .append_header((header::CONTENT_TYPE, "application/json"))
+                .append_header((header::AUTHORIZATION, "Bearer ".to_string() + &access_token))
+                .send_json(attributes)
+                .await;
+
+            match response {
+                Ok(response) => match response.status() {
+                    StatusCode::NO_CONTENT => Ok(()),
+                    status => Err(admin_status(status))
+                },
+                Err(_) => Err(AdminError::Failed)
+            }
+        }
+    }).await
+}
+
+/// Store the seed as `user-seed` attribute of `user_id`, sealed to the user.
+/// The other attributes of the user are kept.
+async fn put_user_seed(user_id: &String, seed: &String, tokens: &TokenManager) -> Result<(), ()> {
+    let user_info = get_user_info(user_id, tokens).await?;
+    let mut attributes = user_info.attributes.unwrap_or_default().other;
+    attributes.insert("user-seed".to_string(), json!([seal_seed(&tokens.config, user_id, seed)]));
+    let attributes = json!({
+        "attributes": attributes
+    });
+    put_user_attributes(user_id, &attributes, tokens).await
 }
 
 pub async fn insert_seed_user(
     user_id: &String,
-    req: HttpRequest,
-    env: web::Data<Config>
+    seed: &String,
+    tokens: &TokenManager
 ) -> Result<web::Json<InsertUserSeedOutput>, web::Json<InsertUserSeedOutput>> { 
-    let config = env.clone();
-
-    match get_sugarfunge_token(env).await {
-        Ok(response) => {
-            match account::create(req).await {
-                Ok(response_account) => {                    
-                    
-                    let bytes = body::to_bytes(response_account.into_body()).await.unwrap();
-                    let str_bytes = std::str::from_utf8(&bytes).unwrap().to_string();
-                    let body: CreateAccountOutput = serde_json::from_str(&str_bytes).unwrap();
-                    // println!("CreateAccountOutput {:?}", body);
-                    
-                    let awc_client = awc::Client::new();
-                    let endpoint = format!("{}/auth/admin/realms/{}/users/{}", config.keycloak_host, config.keycloak_realm, user_id); 
-
-                    let attributes = json!({
-                        "attributes": {
-                            "user-seed": [
-                                body.seed
-                            ]
-                        }
-                    });
-        
-                    let response = awc_client.put(endpoint)
-                        .append_header((header::ACCEPT, "application/json"), )
-                        .append_header((header::CONTENT_TYPE, "application/json"))
-                        .append_header((header::AUTHORIZATION, "Bearer ".to_string() + &response.access_token))
-                        .send_json(&attributes)
-                        .await;
-        
-                    match response {
-                        Ok(response) => {
-                            match response.status() { 
-                                StatusCode::NO_CONTENT => {
-                                    Ok(web::Json(
-                                        InsertUserSeedOutput {
-                                            error: None,
-                                            message: "Attribute insert to user attributes".to_string()
-                                        }
-                                    ))
-                                }
-                                _ => {
-                                    Err(web::Json(
-                                        InsertUserSeedOutput {
-                                        error: Some("Error Insert Attribute".to_string()),
-                                        message: "Error when insert attribute to user".to_string()
-                                    }
-                                  ))
-                                }
-                            }
-                        }
-                        Err(_e) => {
-                            Err(web::Json(
-                                InsertUserSeedOutput {
-                                    error: Some("Error Insert Attribute".to_string()),
-                                    message: "Unknown Error".to_string()
-                                }
-                          ))
-                        }
-                    }
-                }
-                Err(_) => {
-                    Err(web::Json(
-                        InsertUserSeedOutput {
-                            error: Some("Error Insert Attribute".to_string()),
-                            message: "Unknown Error".to_string()
-                        }
-                    ))
+    match put_user_seed(user_id, seed, tokens).await {
+        Ok(_) => {
+            Ok(web::Json(
+                InsertUserSeedOutput {
+                    error: None,
+                    message: "Attribute insert to user attributes".to_string()
                 }
-            }            
-
+            ))
         }
-        Err(_error) => {
+        Err(_) => {
             Err(web::Json(
                 InsertUserSeedOutput {
                     error: Some("Error Insert Attribute".to_string()),
-                    message: "Unknown Error".to_string()
+                    message: "Error when insert attribute to user".to_string()
                 }
             ))
         }
     }
 }
 
+/// Insert the seed into `to_id`, then remove the seed attribute of `from_id`.
+/// The other attributes of both users are kept.
+pub async fn move_seed_user(
+    from_id: &String,
+    to_id: &String,
+    seed: &String,
+    tokens: &TokenManager
+) -> Result<(), ()> {
+    put_user_seed(to_id, seed, tokens).await?;
+
+    let cleared = match get_user_info(from_id, tokens).await {
+        Ok(from_info) => {
+            let attributes = json!({
+                "attributes": from_info.attributes.unwrap_or_default().other
+            });
+            put_user_attributes(from_id, &attributes, tokens).await
+        }
+        Err(_) => Err(())
+    };
+    if cleared.is_err() {
+        log::error!("Seed of {} copied to {} but not removed from {}", from_id, to_id, from_id);
+    }
+    cleared
+}
+
+/// Re-encrypt the `user-seed` attribute of every user with the current master key
+pub async fn rotate_seeds(tokens: &TokenManager) -> Result<usize, String> {
+    let config = &tokens.config;
+    let keys = config.seed_master_keys.clone().ok_or("SEED_MASTER_KEYS enviroment variable is not set")?;
+
+    let page_size = 100;
+    let mut first = 0;
+    let mut rotated = 0;
+    loop {
+        let endpoint = format!("{}/auth/admin/realms/{}/users?first={}&max={}&briefRepresentation=false", config.keycloak_host, config.keycloak_realm, first, page_size);
+
+        let users: Vec<UserInfo> = tokens.with_token(|access_token| {
+            let endpoint = endpoint.clone();
+            async move {
+                let awc_client = awc::Client::new();
+                let users_response = awc_client.get(endpoint)
+                    .append_header((header::ACCEPT, "application/json"), )
+                    .append_header((header::AUTHORIZATION, "Bearer ".to_string() + &access_token))
+                    .send()
+                    .await;
+
+                match users_response {
+                    Ok(mut users_response) => match users_response.status() {
+                        StatusCode::OK => users_response.json().limit(16 * 1024 * 1024).await.map_err(|_| AdminError::Failed),
+                        status => Err(admin_status(status))
+                    },
+                    Err(_) => Err(AdminError::Failed)
+                }
+            }
+        }).await.map_err(|_| "Error when listing users".to_string())?;
+
+        for user in users.iter() {
+            let user_attributes = user.attributes.clone().unwrap_or_default();
+            let stored = match user_attributes.user_seed.first() {
+                Some(stored) => stored.clone(),
+                None => continue
+            };
+            if !keys.needs_rotation(&stored) {
+                continue;
+            }
+            let seed = keys.open(&stored, &user.id).ok_or(format!("Failed to decrypt seed of user {}", user.id))?;
+            let mut other = user_attributes.other;
+            other.insert("user-seed".to_string(), json!([keys.seal(&seed, &user.id)]));
+            let attributes = json!({
+                "attributes": other
+            });
+            put_user_attributes(&user.id, &attributes, tokens)
+                .await
+                .map_err(|_| format!("Error when updating user {}", user.id))?;
+            rotated += 1;
+        }
+
+        if users.len() < page_size {
+            break;
+        }
+        first += page_size;
+    }
+    Ok(rotated)
+}
+
 #[derive(Debug, Deserialize)]
 #[allow(dead_code)]
 pub struct ClaimsWithEmail {
@@ -288,24 +473,36 @@ pub struct ClaimsWithEmail {
 }
 
 pub async fn verify_seed(
+    data: web::Data<AppState>,
     claims: KeycloakClaims<ClaimsWithEmail>,
-    req: HttpRequest,
-    env: web::Data<Config>
 ) ->  impl Responder { 
 
-    match get_seed(&claims.sub, env.clone()).await {
-        Ok(response) => {
-            if !response.seed.clone().unwrap_or_default().is_empty() {
-                web::Json(
-                    InsertUserSeedOutput {
-                        error: None,
-                        message: "User with atrribute".to_string()
-                    }
-                )
-            } else {
-                match insert_seed_user(&claims.sub, req, env).await {
-                    Ok(response) => { response }
-                    Err(error) => {error}
+    match data.signer.pair(&claims.sub).await {
+        Ok(Some(_)) => {
+            web::Json(
+                InsertUserSeedOutput {
+                    error: None,
+                    message: "User with atrribute".to_string()
+                }
+            )
+        },
+        Ok(None) => {
+            match data.signer.create(&claims.sub).await {
+                Ok(_) => {
+                    web::Json(
+                        InsertUserSeedOutput {
+                            error: None,
+                            message: "Attribute insert to user attributes".to_string()
+                        }
+                    )
+                }
+                Err(_) => {
+                    web::Json(
+                        InsertUserSeedOutput {
+                            error: Some("Error Insert Attribute".to_string()),
+                            message: "Error when insert attribute to user".to_string()
+                        }
+                    )
                 }
             }
         },
@@ -318,4 +515,39 @@ pub async fn verify_seed(
             )
         }
     }
-}
\ No newline at end of file
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UserMetadataInput {
+    metadata: serde_json::Value,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UserMetadataOutput {
+    metadata: serde_json::Value,
+}
+
+pub async fn get_metadata(
+    data: web::Data<AppState>,
+    claims: KeycloakClaims<ClaimsWithEmail>,
+) -> actix_web::error::Result<HttpResponse> {
+    let db = require_db(&data.db)?;
+    let metadata = db.user_metadata(&claims.sub).await.map_err(map_db_err)?;
+    Ok(HttpResponse::Ok().json(UserMetadataOutput {
+        metadata: metadata.unwrap_or(serde_json::Value::Null),
+    }))
+}
+
+pub async fn update_metadata(
+    data: web::Data<AppState>,
+    req: web::Json<UserMetadataInput>,
+    claims: KeycloakClaims<ClaimsWithEmail>,
+) -> actix_web::error::Result<HttpResponse> {
+    let db = require_db(&data.db)?;
+    db.set_user_metadata(&claims.sub, &req.metadata)
+        .await
+        .map_err(map_db_err)?;
+    Ok(HttpResponse::Ok().json(UserMetadataOutput {
+        metadata: req.metadata.clone(),
+    }))
+}
@@ -3,14 +3,15 @@ use derive_more::Display;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use sp_core::Pair;
+use std::str::FromStr;
 
 #[derive(Serialize, Deserialize, Debug, Display)]
 pub struct RequestError {
     pub message: serde_json::Value,
 }
 
-pub fn map_subxt_err(e: subxt::Error) -> actix_web::Error {
-    let json_err: serde_json::Value = match e {
+pub fn subxt_err_message(e: subxt::Error) -> serde_json::Value {
+    match e {
         subxt::Error::Rpc(rpc) => match rpc {
             jsonrpsee_types::error::Error::Request(e) => {
                 serde_json::from_str(&e).unwrap_or(json!(&e))
@@ -26,7 +27,11 @@ pub fn map_subxt_err(e: subxt::Error) -> actix_web::Error {
             _ => json!(e.to_string()),
         },
         _ => json!(e.to_string()),
-    };
+    }
+}
+
+pub fn map_subxt_err(e: subxt::Error) -> actix_web::Error {
+    let json_err: serde_json::Value = subxt_err_message(e);
     let req_error = RequestError { message: json_err };
     let req_error = serde_json::to_string_pretty(&req_error).unwrap();
     error::ErrorBadRequest(req_error)
@@ -39,6 +44,23 @@ pub fn map_account_err(_e: sp_core::crypto::PublicError) -> actix_web::Error {
     error::ErrorBadRequest(req_error)
 }
 
+pub fn map_cursor_err(_e: hex::FromHexError) -> actix_web::Error {
+    let json_err: serde_json::Value = json!("Invalid cursor");
+    let req_error = RequestError { message: json_err };
+    let req_error = serde_json::to_string_pretty(&req_error).unwrap();
+    error::ErrorBadRequest(req_error)
+}
+
+pub fn parse_block_hash(hash: &str) -> error::Result<sp_core::H256> {
+    sp_core::H256::from_str(hash.trim_start_matches("0x")).map_err(|_| {
+        let req_error = RequestError {
+            message: json!("Invalid block hash"),
+        };
+        let req_error = serde_json::to_string_pretty(&req_error).unwrap();
+        error::ErrorBadRequest(req_error)
+    })
+}
+
 pub fn get_pair_from_seed(seed: &str) -> error::Result<sp_core::sr25519::Pair> {
     sp_core::sr25519::Pair::from_string(&seed, None).map_err(|e| {
         let req_error = RequestError {
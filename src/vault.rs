@@ -0,0 +1,270 @@
+use chacha20poly1305::aead::{Aead, NewAead, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+const NONCE_LEN: usize = 24;
+const SALT_LEN: usize = 16;
+
+/// Encrypt `plaintext` with a random nonce, returns hex(nonce ++ ciphertext).
+/// `aad` is authenticated but not encrypted, `open` must be given the same value.
+pub fn seal(cipher: &XChaCha20Poly1305, plaintext: &str, aad: &[u8]) -> String {
+    let nonce = rand::thread_rng().gen::<[u8; NONCE_LEN]>();
+    let payload = Payload {
+        msg: plaintext.as_bytes(),
+        aad,
+    };
+    let ciphertext = cipher
+        .encrypt(XNonce::from_slice(&nonce), payload)
+        .expect("encryption failure");
+    format!("{}{}", hex::encode(nonce), hex::encode(ciphertext))
+}
+
+/// Decrypt a value produced by `seal`
+pub fn open(cipher: &XChaCha20Poly1305, sealed: &str, aad: &[u8]) -> Option<String> {
+    let bytes = hex::decode(sealed).ok()?;
+    if bytes.len() < NONCE_LEN {
+        return None;
+    }
+    let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+    let payload = Payload {
+        msg: ciphertext,
+        aad,
+    };
+    let plaintext = cipher.decrypt(XNonce::from_slice(nonce), payload).ok()?;
+    String::from_utf8(plaintext).ok()
+}
+
+/// Key of version 1 keystores, an unsalted hash of the password. Only used to
+/// read those keystores before they are upgraded.
+pub fn legacy_cipher_from_password(password: &str) -> XChaCha20Poly1305 {
+    let key = sp_core::hashing::blake2_256(password.as_bytes());
+    XChaCha20Poly1305::new(Key::from_slice(&key))
+}
+
+/// Argon2id parameters of a keystore, stored in its header
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct KeystoreKdf {
+    pub algorithm: String,
+    /// Hex encoded random salt
+    pub salt: String,
+    /// Memory cost in KiB
+    pub m_cost: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+}
+
+impl KeystoreKdf {
+    pub const ARGON2ID: &'static str = "argon2id";
+
+    /// Parameters with a new random salt
+    pub fn generate() -> Self {
+        let salt = rand::thread_rng().gen::<[u8; SALT_LEN]>();
+        Self {
+            algorithm: Self::ARGON2ID.to_string(),
+            salt: hex::encode(salt),
+            m_cost: 19 * 1024,
+            t_cost: 2,
+            p_cost: 1,
+        }
+    }
+
+    /// Derive the keystore key from `password`
+    pub fn cipher(&self, password: &str) -> Result<XChaCha20Poly1305, String> {
+        if self.algorithm != Self::ARGON2ID {
+            return Err(format!("Unsupported keystore kdf {}", self.algorithm));
+        }
+        let salt = hex::decode(&self.salt).map_err(|_| "Invalid keystore salt".to_string())?;
+        let params = argon2::Params::new(self.m_cost, self.t_cost, self.p_cost, Some(32))
+            .map_err(|e| format!("Invalid keystore kdf parameters: {}", e))?;
+        let argon2 = argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params);
+        let mut key = [0u8; 32];
+        argon2
+            .hash_password_into(password.as_bytes(), &salt, &mut key)
+            .map_err(|e| format!("Failed to derive keystore key: {}", e))?;
+        Ok(XChaCha20Poly1305::new(Key::from_slice(&key)))
+    }
+}
+
+/// Versioned master keys used to encrypt user seeds at rest.
+///
+/// Sealed seeds are stored as `enc:v<version>:<hex>`, values without the prefix
+/// are legacy plaintext seeds. The Keycloak `sub` of the owner is bound to the
+/// ciphertext as associated data, so a sealed seed cannot be copied to another user.
+#[derive(Clone)]
+pub struct MasterKeys {
+    current: u32,
+    keys: BTreeMap<u32, [u8; 32]>,
+}
+
+impl std::fmt::Debug for MasterKeys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MasterKeys")
+            .field("current", &self.current)
+            .field("versions", &self.keys.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl MasterKeys {
+    /// Parse `<version>:<hex key>` pairs separated by commas, the current
+    /// version defaults to the highest one
+    pub fn parse(keys: &str, current: Option<u32>) -> Result<Self, String> {
+        let mut parsed = BTreeMap::new();
+        for entry in keys.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (version, key) = entry
+                .split_once(':')
+                .ok_or_else(|| format!("Invalid master key entry {}", entry))?;
+            let version: u32 = version
+                .parse()
+                .map_err(|_| format!("Invalid master key version {}", version))?;
+            let key = hex::decode(key.trim_start_matches("0x"))
+                .map_err(|_| format!("Invalid master key v{}", version))?;
+            let key: [u8; 32] = key
+                .try_into()
+                .map_err(|_| format!("Master key v{} must be 32 bytes", version))?;
+            parsed.insert(version, key);
+        }
+        let current = match current {
+            Some(current) => current,
+            None => *parsed.keys().last().ok_or("No master keys")?,
+        };
+        if !parsed.contains_key(&current) {
+            return Err(format!("Master key v{} not found", current));
+        }
+        Ok(Self {
+            current,
+            keys: parsed,
+        })
+    }
+
+    fn cipher(&self, version: u32) -> Option<XChaCha20Poly1305> {
+        self.keys
+            .get(&version)
+            .map(|key| XChaCha20Poly1305::new(Key::from_slice(key)))
+    }
+
+    pub fn seal(&self, seed: &str, sub: &str) -> String {
+        let cipher = self.cipher(self.current).unwrap();
+        format!("enc:v{}:{}", self.current, seal(&cipher, seed, sub.as_bytes()))
+    }
+
+    /// Decrypt the stored seed of `sub`, legacy plaintext seeds are returned as is
+    pub fn open(&self, stored: &str, sub: &str) -> Option<String> {
+        match parse_sealed(stored) {
+            Some((version, sealed)) => open(&self.cipher(version)?, sealed, sub.as_bytes()),
+            None if is_sealed(stored) => None,
+            None => Some(stored.to_string()),
+        }
+    }
+
+    /// Whether a stored seed is not sealed with the current master key
+    pub fn needs_rotation(&self, stored: &str) -> bool {
+        !matches!(parse_sealed(stored), Some((version, _)) if version == self.current)
+    }
+}
+
+/// Whether a stored seed is sealed by `MasterKeys`, as opposed to a legacy plaintext seed
+pub fn is_sealed(stored: &str) -> bool {
+    stored.starts_with("enc:")
+}
+
+fn parse_sealed(stored: &str) -> Option<(u32, &str)> {
+    let rest = stored.strip_prefix("enc:v")?;
+    let (version, sealed) = rest.split_once(':')?;
+    Some((version.parse().ok()?, sealed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_kdf() -> KeystoreKdf {
+        KeystoreKdf {
+            m_cost: 64,
+            t_cost: 1,
+            ..KeystoreKdf::generate()
+        }
+    }
+
+    #[test]
+    fn seal_open_with_aad() {
+        let cipher = test_kdf().cipher("password").unwrap();
+        let sealed = seal(&cipher, "seed", b"alice");
+        assert_eq!(open(&cipher, &sealed, b"alice").as_deref(), Some("seed"));
+        assert_eq!(open(&cipher, &sealed, b"bob"), None);
+    }
+
+    #[test]
+    fn keystore_kdf_is_salted() {
+        let kdf = test_kdf();
+        let sealed = seal(&kdf.cipher("password").unwrap(), "seed", &[]);
+        assert_eq!(open(&kdf.cipher("password").unwrap(), &sealed, &[]).as_deref(), Some("seed"));
+        assert_eq!(open(&kdf.cipher("other").unwrap(), &sealed, &[]), None);
+
+        let other_salt = KeystoreKdf {
+            salt: KeystoreKdf::generate().salt,
+            ..kdf.clone()
+        };
+        assert_eq!(open(&other_salt.cipher("password").unwrap(), &sealed, &[]), None);
+    }
+
+    const KEY_1: &str = "0101010101010101010101010101010101010101010101010101010101010101";
+    const KEY_2: &str = "0202020202020202020202020202020202020202020202020202020202020202";
+
+    #[test]
+    fn master_keys_parse() {
+        let keys = MasterKeys::parse(&format!("1:{}, 2:0x{}", KEY_1, KEY_2), None).unwrap();
+        assert_eq!(keys.current, 2);
+        assert_eq!(keys.keys.len(), 2);
+
+        let keys = MasterKeys::parse(&format!("1:{},2:{}", KEY_1, KEY_2), Some(1)).unwrap();
+        assert_eq!(keys.current, 1);
+
+        assert!(MasterKeys::parse("", None).is_err());
+        assert!(MasterKeys::parse(KEY_1, None).is_err());
+        assert!(MasterKeys::parse("x:00", None).is_err());
+        assert!(MasterKeys::parse("1:zz", None).is_err());
+        assert!(MasterKeys::parse("1:0101", None).is_err());
+        assert!(MasterKeys::parse(&format!("1:{}", KEY_1), Some(2)).is_err());
+    }
+
+    #[test]
+    fn master_keys_seal_open() {
+        let keys = MasterKeys::parse(&format!("1:{}", KEY_1), None).unwrap();
+        let stored = keys.seal("seed", "alice");
+        assert!(stored.starts_with("enc:v1:"));
+        assert!(is_sealed(&stored));
+        assert_eq!(keys.open(&stored, "alice").as_deref(), Some("seed"));
+        assert_eq!(keys.open(&stored, "bob"), None);
+        assert_eq!(keys.open("legacy seed", "alice").as_deref(), Some("legacy seed"));
+        assert_eq!(keys.open("enc:v9:00", "alice"), None);
+        assert_eq!(keys.open("enc:garbage", "alice"), None);
+    }
+
+    #[test]
+    fn master_keys_rotation() {
+        let old = MasterKeys::parse(&format!("1:{}", KEY_1), None).unwrap();
+        let keys = MasterKeys::parse(&format!("1:{},2:{}", KEY_1, KEY_2), None).unwrap();
+        let stored = old.seal("seed", "alice");
+        assert!(!old.needs_rotation(&stored));
+        assert!(keys.needs_rotation(&stored));
+        assert!(keys.needs_rotation("legacy seed"));
+
+        let rotated = keys.seal(&keys.open(&stored, "alice").unwrap(), "alice");
+        assert!(rotated.starts_with("enc:v2:"));
+        assert!(!keys.needs_rotation(&rotated));
+        assert_eq!(keys.open(&rotated, "alice").as_deref(), Some("seed"));
+        assert_eq!(old.open(&rotated, "alice"), None);
+    }
+
+    #[test]
+    fn keystore_kdf_rejects_unknown_algorithm() {
+        let kdf = KeystoreKdf {
+            algorithm: "blake2".to_string(),
+            ..test_kdf()
+        };
+        assert!(kdf.cipher("password").is_err());
+    }
+}